@@ -0,0 +1,142 @@
+use macroquad::prelude::*;
+
+pub const LOD_FUZZYNESS: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub zoom_multiplier: f32,
+}
+
+pub fn world_pos_to_screen_pos(x: f32, y: f32, camera: &CameraSettings) -> (f32, f32) {
+    let out_x = screen_width() / 2. + ((x - camera.x_offset) * camera.zoom_multiplier);
+    let out_y = screen_height() / 2. + ((y - camera.y_offset) * camera.zoom_multiplier);
+    (out_x, out_y)
+}
+
+pub fn screen_pos_to_world_pos(x: f32, y: f32, camera: &CameraSettings) -> (f32, f32) {
+    let x_out = camera.x_offset + (x - screen_width() / 2.) / camera.zoom_multiplier;
+    let y_out = camera.y_offset + (y - screen_height() / 2.) / camera.zoom_multiplier;
+    (x_out, y_out)
+}
+
+pub fn sector_at_screen_pos(
+    x: f32,
+    y: f32,
+    camera: &CameraSettings,
+    tile_dimensions: (f32, f32),
+    lod: usize,
+) -> (i32, i32) {
+    let two: f32 = 2.0;
+    let screen_point_coords = screen_pos_to_world_pos(x, y, camera);
+
+    // get sector x
+    let tile_world_x_size = tile_dimensions.0 * two.powf(lod as f32);
+    let screen_point_sector_x = if screen_point_coords.0 < 0.0 {
+        (screen_point_coords.0 / tile_world_x_size) as i32 - 1
+    } else {
+        (screen_point_coords.0 / tile_world_x_size) as i32
+    };
+
+    // get sector y
+    let tile_world_y_size = tile_dimensions.1 * two.powf(lod as f32);
+    let screen_point_sector_y = if screen_point_coords.1 < 0.0 {
+        (screen_point_coords.1 / tile_world_y_size) as i32 - 1
+    } else {
+        (screen_point_coords.1 / tile_world_y_size) as i32
+    };
+
+    (screen_point_sector_x, screen_point_sector_y)
+}
+
+pub(crate) fn tile_on_screen(
+    tile_data: (i32, i32, usize),
+    camera: &CameraSettings,
+    tile_dimensions: (f32, f32),
+) -> bool {
+    let (tile_x, tile_y, render_lod) = tile_data;
+    // determine what sectors we need to render
+    let (top_left_sector, bottom_right_sector) =
+        get_screen_sectors(camera, tile_dimensions, render_lod);
+
+    // if tile on screen
+    tile_x >= top_left_sector.0
+        && tile_y >= top_left_sector.1
+        && tile_x <= bottom_right_sector.0
+        && tile_y <= bottom_right_sector.1
+}
+
+/// Keeps the center of the screen over an image of the given dimensions, so it can't be panned out of view
+pub fn clamp_to_image(camera: &mut CameraSettings, image_bounds: (u32, u32)) {
+    camera.x_offset = camera.x_offset.clamp(0.0, image_bounds.0 as f32);
+    camera.y_offset = camera.y_offset.clamp(0.0, image_bounds.1 as f32);
+}
+
+/// Centers the screen on the middle of an image of the given dimensions
+pub fn center_on_image(camera: &mut CameraSettings, image_bounds: (u32, u32)) {
+    camera.x_offset = image_bounds.0 as f32 / 2.;
+    camera.y_offset = image_bounds.1 as f32 / 2.;
+}
+
+/// Zooms so that an image of the given dimensions is as large as it can be while fitting on the screen, and centers it
+pub fn fit_to_screen(camera: &mut CameraSettings, image_bounds: (u32, u32)) {
+    let x_zoom = screen_width() / image_bounds.0.max(1) as f32;
+    let y_zoom = screen_height() / image_bounds.1.max(1) as f32;
+    camera.zoom_multiplier = x_zoom.min(y_zoom);
+    center_on_image(camera, image_bounds);
+}
+
+pub fn lod_from_zoom(zoom_multiplier: f32, max_lod: usize) -> usize {
+    let two: f32 = 2.0;
+    let mut lod: usize = 0;
+    for level in 0..=max_lod {
+        if zoom_multiplier < LOD_FUZZYNESS / two.powf(level as f32) {
+            lod = level;
+        } else {
+            break;
+        }
+    }
+    lod
+}
+
+pub fn get_screen_sectors(
+    camera: &CameraSettings,
+    tile_dimensions: (f32, f32),
+    lod: usize,
+) -> ((i32, i32), (i32, i32)) {
+    let top_left_sector = sector_at_screen_pos(0., 0., camera, tile_dimensions, lod);
+
+    let bottom_right_sector = sector_at_screen_pos(
+        screen_width(),
+        screen_height(),
+        camera,
+        tile_dimensions,
+        lod,
+    );
+
+    (top_left_sector, bottom_right_sector)
+}
+
+/// draws a grid over the screen that outlines the size of tiles being currently rendered
+fn _draw_tile_lines(camera: &CameraSettings, lod: usize, tile_dimensions: (f32, f32)) {
+    let (top_left_sector, bottom_right_sector) = get_screen_sectors(camera, tile_dimensions, lod);
+    let two: f32 = 2.0;
+
+    // for all sectors to render
+    for sector_y in top_left_sector.1..=bottom_right_sector.1 {
+        let tile_screen_y = screen_height() / 2.
+            + (-camera.y_offset * camera.zoom_multiplier)
+            + sector_y as f32 * tile_dimensions.1 * camera.zoom_multiplier * two.powf(lod as f32);
+
+        draw_line(0., tile_screen_y, screen_width(), tile_screen_y, 3.0, RED);
+    }
+
+    for sector_x in top_left_sector.0..=bottom_right_sector.0 {
+        let tile_screen_x = screen_width() / 2.
+            + (-camera.x_offset * camera.zoom_multiplier)
+            + sector_x as f32 * tile_dimensions.0 * camera.zoom_multiplier * two.powf(lod as f32);
+
+        draw_line(tile_screen_x, 0., tile_screen_x, screen_height(), 3.0, RED);
+    }
+}
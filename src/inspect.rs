@@ -0,0 +1,148 @@
+use crate::options::InspectArgs;
+use serde_json::json;
+use std::io;
+use tileview::source::{DirSource, TileSource};
+
+fn open(args: &InspectArgs) -> io::Result<DirSource> {
+    DirSource::open_with_layout(
+        &args.tile_dir,
+        args.dir_layout.layout,
+        args.dir_layout.template.as_deref(),
+    )
+}
+
+/// The `(columns, rows)` of each LOD's tile grid
+fn tile_grids(source: &DirSource) -> Vec<(u32, u32)> {
+    (0..source.lod_count())
+        .map(|lod| source.tile_grid(lod).unwrap_or((0, 0)))
+        .collect()
+}
+
+/// Prints the LOD count, tile size, image size and tile grids of a tile directory
+pub fn info(args: &InspectArgs) -> io::Result<()> {
+    let source = open(args)?;
+    let tile_size = source.tile_size();
+    let image_bounds = source.image_bounds().unwrap_or((0, 0));
+    let tile_grids = tile_grids(&source);
+
+    if args.json {
+        let lods: Vec<_> = tile_grids
+            .iter()
+            .enumerate()
+            .map(|(lod, (columns, rows))| json!({ "lod": lod, "columns": columns, "rows": rows }))
+            .collect();
+        let info = json!({
+            "lod_count": source.lod_count(),
+            "tile_size": [tile_size.0, tile_size.1],
+            "image_size": [image_bounds.0, image_bounds.1],
+            "lods": lods,
+        });
+        println!("{:#}", info);
+    } else {
+        println!("LODs: {}", source.lod_count());
+        println!("tile size: {}x{}", tile_size.0, tile_size.1);
+        println!("image size: {}x{}", image_bounds.0, image_bounds.1);
+        for (lod, (columns, rows)) in tile_grids.iter().enumerate() {
+            println!("LOD {}: {}x{} tiles", lod, columns, rows);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every file in a tile directory and prints what is wrong with it. Returns whether nothing was.
+pub fn verify(args: &InspectArgs) -> io::Result<bool> {
+    let source = open(args)?;
+    let report = source.verify()?;
+    let tile_grids = tile_grids(&source);
+
+    if args.json {
+        let lods: Vec<_> = tile_grids
+            .iter()
+            .zip(&report.tiles_found)
+            .enumerate()
+            .map(|(lod, ((columns, rows), tiles_found))| {
+                json!({ "lod": lod, "columns": columns, "rows": rows, "tiles_found": tiles_found })
+            })
+            .collect();
+        let missing: Vec<_> = report
+            .missing
+            .iter()
+            .map(|(x, y, lod)| json!({ "x": x, "y": y, "lod": lod }))
+            .collect();
+        let wrong_dimensions: Vec<_> = report
+            .wrong_dimensions
+            .iter()
+            .map(|(path, size, expected)| {
+                json!({
+                    "path": path.to_string_lossy(),
+                    "size": [size.0, size.1],
+                    "expected": [expected.0, expected.1],
+                })
+            })
+            .collect();
+        let undecodable: Vec<_> = report
+            .undecodable
+            .iter()
+            .map(|(path, error)| json!({ "path": path.to_string_lossy(), "error": error }))
+            .collect();
+        let stray: Vec<_> = report
+            .stray
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect();
+        let verification = json!({
+            "consistent": report.is_consistent(),
+            "lods": lods,
+            "missing": missing,
+            "wrong_dimensions": wrong_dimensions,
+            "undecodable": undecodable,
+            "stray": stray,
+        });
+        println!("{:#}", verification);
+    } else {
+        for (lod, ((columns, rows), tiles_found)) in
+            tile_grids.iter().zip(&report.tiles_found).enumerate()
+        {
+            println!(
+                "LOD {}: {} of {} tiles ({}x{})",
+                lod,
+                tiles_found,
+                *columns as usize * *rows as usize,
+                columns,
+                rows
+            );
+        }
+        for (x, y, lod) in &report.missing {
+            println!("missing: LOD {} tile {},{}", lod, x, y);
+        }
+        for (path, size, expected) in &report.wrong_dimensions {
+            println!(
+                "wrong dimensions: {} is {}x{}, expected {}x{}",
+                path.display(),
+                size.0,
+                size.1,
+                expected.0,
+                expected.1
+            );
+        }
+        for (path, error) in &report.undecodable {
+            println!("undecodable: {}: {}", path.display(), error);
+        }
+        for path in &report.stray {
+            println!("stray: {}", path.display());
+        }
+
+        let problems = report.missing.len()
+            + report.wrong_dimensions.len()
+            + report.undecodable.len()
+            + report.stray.len();
+        if problems == 0 {
+            println!("no problems found");
+        } else {
+            println!("{} problems found", problems);
+        }
+    }
+
+    Ok(report.is_consistent())
+}
@@ -0,0 +1,13 @@
+//! Renders super resolution images by only drawing the tiles that are on screen, at a level of detail that matches
+//! the current zoom.
+//!
+//! Create a [`TileViewer`] from a [`source::TileSource`], then every frame call [`TileViewer::update`] and
+//! [`TileViewer::render`] with the current [`camera::CameraSettings`].
+
+pub mod camera;
+mod loader;
+pub mod source;
+pub mod tiler;
+mod viewer;
+
+pub use viewer::{TileViewer, ViewerSettings, DEFAULT_CACHE_BUDGET_BYTES};
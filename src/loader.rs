@@ -0,0 +1,111 @@
+use crate::source::TileSource;
+use image::RgbaImage;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What became of a tile a worker thread was asked to load
+pub(crate) enum LoadOutcome {
+    /// The tile was read and decoded, ready to be uploaded to the GPU
+    Loaded(RgbaImage),
+    /// The source has no such tile
+    Missing,
+    /// The tile doesn't exist yet, but will soon, such as while the source is still being generated
+    NotReady,
+    /// Reading or decoding the tile failed, which may not happen if it is tried again
+    Failed,
+    /// The request was cancelled before the tile was read or decoded
+    Cancelled,
+}
+
+pub(crate) struct LoadedTile {
+    pub tile: (i32, i32, usize),
+    pub outcome: LoadOutcome,
+}
+
+/// A tile to load, and the flag that cancels loading it
+type TileRequest = ((i32, i32, usize), Arc<AtomicBool>);
+
+/// A pool of worker threads that read tiles from a `TileSource` and decode them to RGBA.
+///
+/// Tiles are requested with `request()`, and returned in the order they finish through `try_recv()`. Setting a
+/// request's cancellation flag makes the workers skip it if they haven't read or decoded it yet. The workers stop once
+/// the loader is dropped and they have finished the tile they are working on.
+pub(crate) struct TileLoader {
+    requests_tx: Sender<TileRequest>,
+    results_rx: Receiver<LoadedTile>,
+}
+
+impl TileLoader {
+    pub fn new<S: TileSource + ?Sized + 'static>(source: Arc<S>, threads: usize) -> Self {
+        let (requests_tx, requests_rx) = mpsc::channel::<TileRequest>();
+        let (results_tx, results_rx) = mpsc::channel();
+        let requests_rx = Arc::new(Mutex::new(requests_rx));
+
+        for _ in 0..threads.max(1) {
+            let source = source.clone();
+            let requests_rx = requests_rx.clone();
+            let results_tx = results_tx.clone();
+            thread::spawn(move || loop {
+                // stop once the loader has been dropped
+                let (tile, cancelled) = match requests_rx.lock().unwrap().recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                };
+                let is_cancelled = || cancelled.load(Ordering::Relaxed);
+
+                let (x, y, lod) = tile;
+                let mut outcome = LoadOutcome::Cancelled;
+                if !is_cancelled() {
+                    let tile_data = source.read_tile(x, y, lod);
+                    // decoding is the slow part, so check again before starting it
+                    if !is_cancelled() {
+                        outcome = match tile_data {
+                            Ok(tile_data) => match tile_data.into_rgba() {
+                                Ok(image) => LoadOutcome::Loaded(image),
+                                Err(_) => LoadOutcome::Failed,
+                            },
+                            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                                LoadOutcome::Missing
+                            }
+                            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                                LoadOutcome::NotReady
+                            }
+                            Err(_) => LoadOutcome::Failed,
+                        };
+                    }
+                }
+
+                let loaded_tile = LoadedTile { tile, outcome };
+                if results_tx.send(loaded_tile).is_err() {
+                    break;
+                }
+            });
+        }
+
+        TileLoader {
+            requests_tx,
+            results_rx,
+        }
+    }
+
+    /// Queues a tile to be loaded by the next free worker, unless `cancelled` is set before then
+    pub fn request(&self, tile: (i32, i32, usize), cancelled: Arc<AtomicBool>) {
+        self.requests_tx.send((tile, cancelled)).unwrap();
+    }
+
+    /// Returns a loaded tile, if any have finished loading
+    pub fn try_recv(&self) -> Option<LoadedTile> {
+        self.results_rx.try_recv().ok()
+    }
+}
+
+/// How many worker threads to use if not told otherwise. Leaves one core for rendering.
+pub(crate) fn default_thread_count() -> usize {
+    thread::available_parallelism()
+        .map(|threads| threads.get().saturating_sub(1))
+        .unwrap_or(1)
+        .max(1)
+}
@@ -1,200 +1,27 @@
-use futures::executor::LocalPool;
-use futures::task::LocalSpawnExt;
 use macroquad::prelude::*;
-use std::collections::VecDeque;
-use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
-use std::{
-    collections::HashMap,
-    path::PathBuf,
-    sync::mpsc::{self, Sender},
-};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use tempdir::TempDir;
-use tileproc::args::GenTilesArgs;
-use tileproc::tiler::{gen_tiles_to_dir, generate_lods};
+use tileview::camera::{
+    center_on_image, clamp_to_image, fit_to_screen, lod_from_zoom, screen_pos_to_world_pos,
+    CameraSettings, LOD_FUZZYNESS,
+};
+use tileview::source::{
+    is_iiif_location, ArchiveSource, DirSource, DziSource, IiifSource, LazyImageSource,
+    MbTilesSource, PmTilesSource, TiffSource, TileSource, ZoomifySource,
+};
+use tileview::tiler::{Tiler, TilingProgress};
+use tileview::{TileViewer, ViewerSettings};
+mod inspect;
 mod options;
+mod tile_cache;
 use clap::Parser;
-
-const LOD_FUZZYNESS: f32 = 1.0;
-
-fn world_pos_to_screen_pos(x: f32, y: f32, camera: &CameraSettings) -> (f32, f32) {
-    let out_x = screen_width() / 2. + ((x - camera.x_offset) * camera.zoom_multiplier);
-    let out_y = screen_height() / 2. + ((y - camera.y_offset) * camera.zoom_multiplier);
-    (out_x, out_y)
-}
-
-fn screen_pos_to_world_pos(x: f32, y: f32, camera: &CameraSettings) -> (f32, f32) {
-    let x_out = camera.x_offset + (x - screen_width() / 2.) / camera.zoom_multiplier;
-    let y_out = camera.y_offset + (y - screen_height() / 2.) / camera.zoom_multiplier;
-    (x_out, y_out)
-}
-
-fn sector_at_screen_pos(
-    x: f32,
-    y: f32,
-    camera: &CameraSettings,
-    tile_dimensions: (f32, f32),
-    lod: usize,
-) -> (i32, i32) {
-    let two: f32 = 2.0;
-    let screen_point_coords = screen_pos_to_world_pos(x, y, camera);
-
-    // get sector x
-    let tile_world_x_size = tile_dimensions.0 * two.powf(lod as f32);
-    let screen_point_sector_x = if screen_point_coords.0 < 0.0 {
-        (screen_point_coords.0 / tile_world_x_size) as i32 - 1
-    } else {
-        (screen_point_coords.0 / tile_world_x_size) as i32
-    };
-
-    // get sector y
-    let tile_world_y_size = tile_dimensions.1 * two.powf(lod as f32);
-    let screen_point_sector_y = if screen_point_coords.1 < 0.0 {
-        (screen_point_coords.1 / tile_world_y_size) as i32 - 1
-    } else {
-        (screen_point_coords.1 / tile_world_y_size) as i32
-    };
-
-    (screen_point_sector_x, screen_point_sector_y)
-}
-
-/// stores texture in texture_cache. Does not check if it is already there.
-async fn cache_texture(
-    tile_dir: PathBuf,
-    tile_data: (i32, i32, usize),
-    results_tx: Sender<((i32, i32, usize), Option<Texture2D>)>,
-) {
-    let (sector_x, sector_y, lod) = tile_data;
-
-    let texture_dir = tile_dir
-        .to_path_buf()
-        .join(lod.to_string())
-        .join(sector_x.to_string() + "," + &sector_y.to_string() + ".png");
-
-    let texture_option =
-        match load_texture(&texture_dir.into_os_string().into_string().unwrap()).await {
-            Ok(texture) => Some(texture),
-            _ => None,
-        };
-
-    results_tx.send((tile_data, texture_option)).unwrap();
-}
-
-fn tile_on_screen(
-    tile_data: (i32, i32, usize),
-    camera: &CameraSettings,
-    tile_dimensions: (f32, f32),
-) -> bool {
-    let (tile_x, tile_y, render_lod) = tile_data;
-    // determine what sectors we need to render
-    let (top_left_sector, bottom_right_sector) =
-        get_screen_sectors(camera, tile_dimensions, render_lod);
-
-    // if tile on screen
-    tile_x >= top_left_sector.0
-        && tile_y >= top_left_sector.1
-        && tile_x <= bottom_right_sector.0
-        && tile_y <= bottom_right_sector.1
-}
-
-fn lod_from_zoom(zoom_multiplier: f32, max_lod: usize) -> usize {
-    let two: f32 = 2.0;
-    let mut lod: usize = 0;
-    for level in 0..=max_lod {
-        if zoom_multiplier < LOD_FUZZYNESS / two.powf(level as f32) {
-            lod = level;
-        } else {
-            break;
-        }
-    }
-    lod
-}
-
-/// determine if current desired view is fully cached and ready to be rendered
-fn current_view_cached(
-    texture_cache: &HashMap<(i32, i32, usize), Option<Texture2D>>,
-    render_lod: usize,
-    camera: &CameraSettings,
-    tile_dimensions: (f32, f32),
-) -> bool {
-    // determine what sectors we need to render
-    let (top_left_sector, bottom_right_sector) =
-        get_screen_sectors(camera, tile_dimensions, render_lod);
-
-    let mut fully_rendered = true;
-    for sector_y in top_left_sector.1..=bottom_right_sector.1 {
-        for sector_x in top_left_sector.0..=bottom_right_sector.0 {
-            // render texture
-            if texture_cache
-                .get(&(sector_x, sector_y, render_lod))
-                .is_none()
-            {
-                fully_rendered = false;
-                break;
-            }
-        }
-        if !fully_rendered {
-            break;
-        }
-    }
-
-    fully_rendered
-}
-
-fn get_screen_sectors(
-    camera: &CameraSettings,
-    tile_dimensions: (f32, f32),
-    lod: usize,
-) -> ((i32, i32), (i32, i32)) {
-    let top_left_sector = sector_at_screen_pos(0., 0., camera, tile_dimensions, lod);
-
-    let bottom_right_sector = sector_at_screen_pos(
-        screen_width(),
-        screen_height(),
-        camera,
-        tile_dimensions,
-        lod,
-    );
-
-    (top_left_sector, bottom_right_sector)
-}
-
-fn average(numbers: &VecDeque<f64>) -> f64 {
-    numbers.iter().sum::<f64>() / numbers.len() as f64
-}
-
-fn new_rolling_average(new_value: f64, rolling_decode_buffer: &mut VecDeque<f64>) -> f64 {
-    rolling_decode_buffer.push_back(new_value);
-
-    if rolling_decode_buffer.len() > 100 {
-        rolling_decode_buffer.pop_front();
-    }
-
-    average(rolling_decode_buffer)
-}
-
-/// draws a grid over the screen that outlines the size of tiles being currently rendered
-fn _draw_tile_lines(camera: &CameraSettings, lod: usize, tile_dimensions: (f32, f32)) {
-    let (top_left_sector, bottom_right_sector) = get_screen_sectors(camera, tile_dimensions, lod);
-    let two: f32 = 2.0;
-
-    // for all sectors to render
-    for sector_y in top_left_sector.1..=bottom_right_sector.1 {
-        let tile_screen_y = screen_height() / 2.
-            + (-camera.y_offset * camera.zoom_multiplier)
-            + sector_y as f32 * tile_dimensions.1 * camera.zoom_multiplier * two.powf(lod as f32);
-
-        draw_line(0., tile_screen_y, screen_width(), tile_screen_y, 3.0, RED);
-    }
-
-    for sector_x in top_left_sector.0..=bottom_right_sector.0 {
-        let tile_screen_x = screen_width() / 2.
-            + (-camera.x_offset * camera.zoom_multiplier)
-            + sector_x as f32 * tile_dimensions.0 * camera.zoom_multiplier * two.powf(lod as f32);
-
-        draw_line(tile_screen_x, 0., tile_screen_x, screen_height(), 3.0, RED);
-    }
-}
+use options::{Cli, Command, InitialView, TileArgs, ViewArgs};
+use tile_cache::{CacheEntry, TileCache};
 
 fn median(numbers: &mut [i32]) -> i32 {
     numbers.sort();
@@ -212,392 +39,184 @@ async fn infer_target_fps() -> i32 {
     median(&mut fps_records)
 }
 
-struct CameraSettings {
-    x_offset: f32,
-    y_offset: f32,
-    zoom_multiplier: f32,
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
 }
 
-// Channel types used to send results of retrieving tiles.
-type TileSender = std::sync::mpsc::Sender<((i32, i32, usize), Option<Texture2D>)>;
-type TileReceiver = std::sync::mpsc::Receiver<((i32, i32, usize), Option<Texture2D>)>;
-
-struct TileViewer {
-    texture_cache: HashMap<(i32, i32, usize), Option<Texture2D>>,
-    retriving_pools: HashMap<(i32, i32, usize), LocalPool>,
-    tile_dimensions: (f32, f32),
-    max_lod: usize,
-    results_tx: TileSender,
-    results_rx: TileReceiver,
-    rolling_decode_buffer: VecDeque<f64>,
-    rolling_average_decode_time: f64,
-    tile_dir: PathBuf,
+/// Views an image file, either by converting it to tiles or by cutting them from it in memory if `--lazy` is set
+fn open_image(
+    image_path: &Path,
+    args: &ViewArgs,
+    image_cache: Option<&TileCache>,
+    tiling_progress: &mut Option<Arc<TilingProgress>>,
+) -> io::Result<Arc<dyn TileSource>> {
+    if args.lazy {
+        Ok(Arc::new(LazyImageSource::open(
+            image_path,
+            args.tiling.tile_size,
+        )?))
+    } else {
+        tile_image(image_path, args, image_cache, tiling_progress)
+    }
 }
 
-impl TileViewer {
-    async fn new(tile_dir: &Path) -> Self {
-        let (results_tx, results_rx): (TileSender, TileReceiver) = mpsc::channel();
-        TileViewer {
-            texture_cache: HashMap::new(),
-            retriving_pools: HashMap::new(),
-            tile_dimensions: {
-                // return dimentions of a tile in lod 0
-
-                // get a tile from lod 0
-                let mut paths = fs::read_dir(tile_dir.to_path_buf().join(0.to_string())).unwrap();
-                let path = paths.next().unwrap().unwrap().path();
-                let path_string = path.to_str().unwrap();
-                let initial_texture: Texture2D = load_texture(path_string).await.unwrap();
-
-                // get the dimensions of the tile before it is freed
-                let tile_dimensions = (initial_texture.width(), initial_texture.height());
-                initial_texture.delete();
-
-                tile_dimensions
-            },
-            max_lod: max_lod_in_tile_dir(tile_dir),
-            results_tx,
-            results_rx,
-            rolling_decode_buffer: VecDeque::new(),
-            rolling_average_decode_time: 0.0,
-            tile_dir: tile_dir.to_path_buf(),
-        }
-    }
+/// Converts an image file to tiles on a background thread, and returns a source that reads the tiles as they are
+/// written. If the image was cached by a previous launch, its tiles are read straight away instead.
+///
+/// The tiles are written to `--keep-tiles` if it is set, and otherwise to the image cache, or a temporary directory if
+/// there is none.
+fn tile_image(
+    image_path: &Path,
+    args: &ViewArgs,
+    image_cache: Option<&TileCache>,
+    tiling_progress: &mut Option<Arc<TilingProgress>>,
+) -> io::Result<Arc<dyn TileSource>> {
+    let settings = args.tiling.settings();
+    let temp_dir = || TempDir::new("tile-viewer").map(TempDir::into_path);
+    let (tile_dir, cache_entry) = match (&args.keep_tiles, image_cache) {
+        (Some(keep_tiles), _) => (keep_tiles.clone(), None),
+        (None, Some(image_cache)) => match image_cache.entry(image_path, &settings)? {
+            CacheEntry::Complete(tile_dir) => return Ok(Arc::new(DirSource::open(&tile_dir)?)),
+            CacheEntry::Empty(entry) => (entry.dir().to_path_buf(), Some(entry)),
+            // another instance is converting the same image, so convert it separately rather than disturb it
+            CacheEntry::InProgress => (temp_dir()?, None),
+        },
+        (None, None) => (temp_dir()?, None),
+    };
 
-    /// Queues tiles from the current LOD that should be rendered on screen, for being retrieved and stored in cache, if they are not already.
-    fn queue_desired_textures(&mut self, camera: &CameraSettings) {
-        let lod = lod_from_zoom(camera.zoom_multiplier, self.max_lod);
-        let (top_left_sector, bottom_right_sector) =
-            get_screen_sectors(camera, self.tile_dimensions, lod);
-
-        // for all sectors to render
-        for sector_y in top_left_sector.1..=bottom_right_sector.1 {
-            for sector_x in top_left_sector.0..=bottom_right_sector.0 {
-                // if tile not in cache
-                if self.texture_cache.get(&(sector_x, sector_y, lod)).is_none() {
-                    // if not actively retrieving
-                    if self
-                        .retriving_pools
-                        .get(&(sector_x, sector_y, lod))
-                        .is_none()
-                    {
-                        let f = cache_texture(
-                            self.tile_dir.clone(),
-                            (sector_x, sector_y, lod),
-                            self.results_tx.clone(),
-                        );
-
-                        // create LocalPool with one task inside
-                        let pool = LocalPool::new();
-                        let spawner = pool.spawner();
-                        spawner.spawn_local(f).unwrap();
-
-                        self.retriving_pools.insert((sector_x, sector_y, lod), pool);
-                    }
-                }
-            }
+    // if anything fails from here on, dropping the cache entry removes it so the next launch converts the image again
+    let tiler = Tiler::new(image_path, &tile_dir, settings)?;
+    let source = tiler.source();
+    *tiling_progress = Some(tiler.progress());
+
+    thread::spawn(move || {
+        let result = tiler
+            .run()
+            .and_then(|()| cache_entry.map_or(Ok(()), |entry| entry.finish()));
+        if let Err(error) = result {
+            eprintln!("failed to convert image to tiles: {}", error);
         }
-    }
+    });
 
-    /// Removes unused tiles from texture_cache
-    ///
-    /// Removes any tiles in cache that are not visible on screen.
-    ///
-    /// Removes all tiles not in the desired LOD, only when the tile cache contains a full screen of tiles from the desired LOD.
-    fn clean_tile_texture_cache(&mut self, camera: &CameraSettings) {
-        let lod = lod_from_zoom(camera.zoom_multiplier, self.max_lod);
-
-        // determine what sectors we need to render
-        let (top_left_sector, bottom_right_sector) =
-            get_screen_sectors(camera, self.tile_dimensions, lod);
-
-        // remove tiles out of view
-        {
-            let mut to_remove = Vec::new();
-            for (tile_data, _) in self.texture_cache.iter() {
-                if !tile_on_screen(*tile_data, camera, self.tile_dimensions) {
-                    to_remove.push(*tile_data);
-                }
-            }
+    Ok(Arc::new(source))
+}
 
-            // remove tiles
-            for (sec_x, sec_y, sec_lod) in to_remove {
-                if let Some(texture) = self.texture_cache.remove(&(sec_x, sec_y, sec_lod)).unwrap()
-                {
-                    texture.delete();
-                }
+fn main() {
+    match Cli::parse().into_command() {
+        Command::View(args) => macroquad::Window::new("TileView", view(args)),
+        Command::Tile(args) => {
+            if let Err(error) = tile(&args) {
+                eprintln!("failed to convert image to tiles: {}", error);
+                process::exit(1);
             }
         }
-
-        //determine if current desired view is fully rendered
-        let fully_rendered =
-            current_view_cached(&self.texture_cache, lod, camera, self.tile_dimensions);
-
-        // possibly remove tiles in wrong lod
-        {
-            // clear texture cache only if fully rendering what we want to be
-            if fully_rendered {
-                // find tiles to remove
-                let mut to_remove = Vec::new();
-                for ((sec_x, sec_y, sec_lod), _) in self.texture_cache.iter() {
-                    if !((lod == *sec_lod)
-                        && (*sec_y >= top_left_sector.1 && *sec_y <= bottom_right_sector.1)
-                        && (*sec_x >= top_left_sector.0 && *sec_x <= bottom_right_sector.0))
-                    {
-                        to_remove.push((*sec_x, *sec_y, *sec_lod));
-                    }
-                }
-
-                // remove tiles
-                for (sec_x, sec_y, sec_lod) in to_remove {
-                    if let Some(texture) =
-                        self.texture_cache.remove(&(sec_x, sec_y, sec_lod)).unwrap()
-                    {
-                        texture.delete();
-                    }
-                }
+        Command::Info(args) => {
+            if let Err(error) = inspect::info(&args) {
+                eprintln!("failed to read tile directory: {}", error);
+                process::exit(1);
             }
         }
-    }
-
-    /// Renders image tiles and returns how many are currently being rendered
-    ///
-    /// Renders all image tiles in tile cache that are on screen. Including tiles with an LOD different from the current one.
-    /// Larger LOD tiles are rendered first, so as to fill in holes left by smaller LOD tiles that have not been cached yet.
-    fn render_screen_tiles(
-        &self,
-        camera: &CameraSettings,
-        tile_boxes: bool,
-        show_culling: bool,
-    ) -> u32 {
-        let mut num_rendered_tiles: u32 = 0;
-        let two: f32 = 2.0;
-
-        for render_lod in (0..=self.max_lod).rev() {
-            // determine what sectors we need to render
-            let (top_left_sector, bottom_right_sector) =
-                get_screen_sectors(camera, self.tile_dimensions, render_lod);
-
-            // for all cached tiles
-            for ((tile_x, tile_y, tile_lod), texture_option) in &self.texture_cache {
-                // if correct LOD
-                if *tile_lod == render_lod {
-                    let tile_on_screen = if !show_culling {
-                        *tile_x >= top_left_sector.0
-                            && *tile_y >= top_left_sector.1
-                            && *tile_x <= bottom_right_sector.0
-                            && *tile_y <= bottom_right_sector.1
-                    } else {
-                        // Define a helper closure for culling logic
-                        let is_within = |coord: i32, start: i32, end: i32| {
-                            if end - start > 1 {
-                                // Multiple tiles on this axis; apply culling
-                                coord > start && coord < end
-                            } else {
-                                // Only one or two tiles on this axis; allow both
-                                coord == start || coord == end
-                            }
-                        };
-
-                        // Check both axes
-                        is_within(*tile_x, top_left_sector.0, bottom_right_sector.0)
-                            && is_within(*tile_y, top_left_sector.1, bottom_right_sector.1)
-                    };
-
-                    // if tile on screen
-                    if tile_on_screen {
-                        // if there's a texture to be rendered
-                        if let Some(texture) = texture_option {
-                            let tile_world_width =
-                                self.tile_dimensions.0 * two.powf(render_lod as f32);
-                            let tile_world_height =
-                                self.tile_dimensions.1 * two.powf(render_lod as f32);
-
-                            let tile_screen_width = tile_world_width * camera.zoom_multiplier;
-                            let tile_screen_height = tile_world_height * camera.zoom_multiplier;
-
-                            let tile_world_x = tile_world_width * *tile_x as f32;
-                            let tile_world_y = tile_world_height * *tile_y as f32;
-
-                            let (tile_screen_x, tile_screen_y) =
-                                world_pos_to_screen_pos(tile_world_x, tile_world_y, camera);
-
-                            let params = DrawTextureParams {
-                                dest_size: Some(vec2(tile_screen_width, tile_screen_height)),
-                                source: None,
-                                rotation: 0.,
-                                flip_x: false,
-                                flip_y: false,
-                                pivot: None,
-                            };
-
-                            if camera.zoom_multiplier >= 1.0 {
-                                texture.set_filter(FilterMode::Nearest);
-                            } else {
-                                texture.set_filter(FilterMode::Linear);
-                            }
-
-                            draw_texture_ex(*texture, tile_screen_x, tile_screen_y, WHITE, params);
-
-                            if tile_boxes {
-                                // draw red box around newly rendered tile
-
-                                // top
-                                draw_line(
-                                    tile_screen_x,
-                                    tile_screen_y,
-                                    tile_screen_x + tile_screen_width,
-                                    tile_screen_y,
-                                    3.0,
-                                    RED,
-                                );
-                                // bottom
-                                draw_line(
-                                    tile_screen_x,
-                                    tile_screen_y + tile_screen_height,
-                                    tile_screen_x + tile_screen_width,
-                                    tile_screen_y + tile_screen_height,
-                                    3.0,
-                                    RED,
-                                );
-                                // left
-                                draw_line(
-                                    tile_screen_x,
-                                    tile_screen_y,
-                                    tile_screen_x,
-                                    tile_screen_y + tile_screen_height,
-                                    3.0,
-                                    RED,
-                                );
-                                // right
-                                draw_line(
-                                    tile_screen_x + tile_screen_width,
-                                    tile_screen_y,
-                                    tile_screen_x + tile_screen_width,
-                                    tile_screen_y + tile_screen_height,
-                                    3.0,
-                                    RED,
-                                );
-                            }
-
-                            num_rendered_tiles += 1;
-                        }
-                    }
-                }
+        Command::Verify(args) => match inspect::verify(&args) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(error) => {
+                eprintln!("failed to read tile directory: {}", error);
+                process::exit(2);
             }
-        }
-        num_rendered_tiles
+        },
     }
+}
 
-    /// Retrieves tiles requested by queue_desired_textures() and stores them in texture_cache
-    ///
-    /// Always retrieves at least one tile, assuming at least one needs to be retrieved.
-    ///
-    /// Retrieves more tiles if there is time to do so before the next frame needs to be rendered.
-    fn retrieve_tiles_till_out_of_work_or_time(
-        &mut self,
-        camera: &CameraSettings,
-        frame_start_time: f64,
-        frame_time_limit: f64,
-    ) {
-        let lod = lod_from_zoom(camera.zoom_multiplier, self.max_lod);
-
-        // stop retrieving any tiles that are not current desired lod
-        self.retriving_pools
-            .retain(|(_, _, tile_lod), _| *tile_lod == lod);
-
-        // possibly prepair one tile
-        let mut finished_tiles = Vec::new();
-        let mut textures_decoded = 0;
-        for ((tile_x, tile_y, tile_lod), pool) in &mut self.retriving_pools {
-            // stop if out of time. But only if have decoded at least one texture
-            if textures_decoded != 0 {
-                let time_since_last_frame = get_time() - frame_start_time;
-                if time_since_last_frame + self.rolling_average_decode_time > frame_time_limit * 0.7
-                {
-                    break;
-                }
-            }
-
-            if *tile_lod == lod {
-                let tile_decode_start_time = get_time();
-                if pool.try_run_one() {
-                    // don't break unless texture was sent back
-                    if let Ok((details, texture_option)) = self.results_rx.try_recv() {
-                        // mark for removal from self.retriving_pools
-                        finished_tiles.push((*tile_x, *tile_y, *tile_lod));
-
-                        // store in
-                        self.texture_cache.insert(details, texture_option);
-
-                        if texture_option.is_some() {
-                            textures_decoded += 1;
-
-                            let last_time_to_decode = get_time() - tile_decode_start_time;
-
-                            self.rolling_average_decode_time = new_rolling_average(
-                                last_time_to_decode,
-                                &mut self.rolling_decode_buffer,
-                            );
-                        }
-                    }
-                }
-            }
-        }
-
-        // remove any finished tiles
-        for (tile_x, tile_y, tile_lod) in finished_tiles {
-            self.retriving_pools.remove(&(tile_x, tile_y, tile_lod));
-        }
+/// Converts an image file to a tile directory, printing how far along it is
+fn tile(args: &TileArgs) -> io::Result<()> {
+    let tiler = Tiler::new(&args.image_path, &args.tile_dir, args.tiling.settings())?;
+    let progress = tiler.progress();
+    let tiling = thread::spawn(move || tiler.run());
+
+    while !progress.is_finished() {
+        eprint!(
+            "\rconverting to tiles: {}/{}",
+            progress.tiles_done(),
+            progress.tiles_total()
+        );
+        io::stderr().flush()?;
+        thread::sleep(Duration::from_millis(100));
     }
+    tiling.join().unwrap()?;
+    eprintln!(
+        "\rconverting to tiles: {}/{}",
+        progress.tiles_done(),
+        progress.tiles_total()
+    );
+
+    Ok(())
 }
 
-// finds max_lod in a directory containing tile lods
-fn max_lod_in_tile_dir(dir: &Path) -> usize {
-    let mut max_lod: usize = 0;
-    for x in 0.. {
-        if dir.to_path_buf().join(&x.to_string()).is_dir() {
-            max_lod = x;
-        } else {
-            break;
+/// Opens the image or tile set to view with the source that reads its kind
+fn open_source(
+    args: &ViewArgs,
+    image_cache: Option<&TileCache>,
+    tiling_progress: &mut Option<Arc<TilingProgress>>,
+) -> io::Result<Arc<dyn TileSource>> {
+    let tile_dir = &args.image_path;
+    Ok(if is_iiif_location(&tile_dir.to_string_lossy()) {
+        Arc::new(IiifSource::open(&tile_dir.to_string_lossy())?)
+    } else if ZoomifySource::is_zoomify_dir(tile_dir) {
+        Arc::new(ZoomifySource::open(tile_dir)?)
+    } else if tile_dir.is_dir() {
+        Arc::new(DirSource::open_with_layout(
+            tile_dir,
+            args.dir_layout.layout,
+            args.dir_layout.template.as_deref(),
+        )?)
+    } else if has_extension(tile_dir, "dzi") {
+        Arc::new(DziSource::open(tile_dir)?)
+    } else if ArchiveSource::is_archive(tile_dir) {
+        Arc::new(ArchiveSource::open(tile_dir)?)
+    } else if TiffSource::is_tiff(tile_dir) {
+        match TiffSource::open(tile_dir) {
+            Ok(source) => Arc::new(source),
+            // TIFFs that aren't tiled are converted like any other image
+            Err(_) => open_image(tile_dir, args, image_cache, tiling_progress)?,
         }
-    }
-    max_lod
+    } else if has_extension(tile_dir, "mbtiles") {
+        Arc::new(MbTilesSource::open(tile_dir)?)
+    } else if has_extension(tile_dir, "pmtiles") {
+        Arc::new(PmTilesSource::open(tile_dir)?)
+    } else {
+        open_image(tile_dir, args, image_cache, tiling_progress)?
+    })
 }
 
-#[macroquad::main("TileView")]
-async fn main() {
-    let args = options::Args::parse();
-    let tile_dir = args.image_path;
-
-    let (mut tile_viewer, max_lod) = if tile_dir.is_dir() {
-        (
-            TileViewer::new(&tile_dir).await,
-            max_lod_in_tile_dir(&tile_dir),
-        )
+/// Opens an image or tile set in a window
+async fn view(args: ViewArgs) {
+    let tile_dir = args.image_path.clone();
+    let image_cache = if args.image_cache_mb > 0 {
+        TileCache::open_default(args.image_cache_mb * 1024 * 1024)
     } else {
-        let tmp_dir = TempDir::new("tile-viewer").unwrap().path().to_path_buf();
-        fs::create_dir(&tmp_dir).unwrap();
-
-        let mut output_dir = tmp_dir.clone();
-        output_dir.push("0/");
-
-        gen_tiles_to_dir(&GenTilesArgs {
-            input: tile_dir.clone(),
-            output: output_dir,
-            tile_dimensions: 256,
-            x_offset: None,
-            y_offset: None,
-        });
-
-        generate_lods(&tmp_dir);
-
-        (
-            TileViewer::new(&tmp_dir).await,
-            max_lod_in_tile_dir(&tmp_dir),
-        )
+        None
     };
 
+    let mut tiling_progress = None;
+    let source = match open_source(&args, image_cache.as_ref(), &mut tiling_progress) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("failed to open {}: {}", tile_dir.display(), error);
+            process::exit(1);
+        }
+    };
+    let max_lod = source.max_lod();
+    let mut viewer_settings = ViewerSettings::default();
+    if let Some(decode_threads) = args.decode_threads {
+        viewer_settings.decode_threads = decode_threads;
+    }
+    if let Some(cache_mb) = args.cache_mb {
+        viewer_settings.cache_budget_bytes = cache_mb * 1024 * 1024;
+    }
+    let mut tile_viewer = TileViewer::with_settings(source, viewer_settings);
+    let image_bounds = tile_viewer.image_bounds();
+
     let two: f32 = 2.0;
     let default_zoom = 1.0 / two.powf(max_lod as f32 - 1.0);
 
@@ -606,6 +225,16 @@ async fn main() {
         y_offset: 0.,
         zoom_multiplier: default_zoom,
     };
+    if let Some(image_bounds) = image_bounds {
+        match args.initial_view {
+            InitialView::Fit => fit_to_screen(&mut camera, image_bounds),
+            InitialView::ActualSize => {
+                camera.zoom_multiplier = 1.0;
+                center_on_image(&mut camera, image_bounds);
+            }
+            InitialView::Center => center_on_image(&mut camera, image_bounds),
+        }
+    }
 
     let mut mouse_clicked_in_position: Option<(f32, f32)> = None;
     let mut clicked_in_x_offset: f32 = 0.0;
@@ -653,6 +282,21 @@ async fn main() {
                 camera.zoom_multiplier -= zoom_speed;
             }
 
+            // jump to fit, 1:1 pixels or the center of the image
+            if is_key_pressed(KeyCode::F) {
+                if let Some(image_bounds) = image_bounds {
+                    fit_to_screen(&mut camera, image_bounds);
+                }
+            }
+            if is_key_pressed(KeyCode::Key1) {
+                camera.zoom_multiplier = 1.0;
+            }
+            if is_key_pressed(KeyCode::C) {
+                if let Some(image_bounds) = image_bounds {
+                    center_on_image(&mut camera, image_bounds);
+                }
+            }
+
             let min_zoom = LOD_FUZZYNESS / two.powf(max_lod as f32 + 1.0);
             let max_zoom = 20.0;
 
@@ -732,23 +376,44 @@ async fn main() {
             } else {
                 mouse_clicked_in_position = None;
             }
+
+            if args.clamp_pan {
+                if let Some(image_bounds) = image_bounds {
+                    clamp_to_image(&mut camera, image_bounds);
+                }
+            }
         }
         // render tile_viewer
         let num_rendered_tiles = {
             clear_background(GRAY);
 
-            // tile_viewer.recieve_retrieved_tiles();
-            tile_viewer.clean_tile_texture_cache(&camera);
-            tile_viewer.queue_desired_textures(&camera);
-            let num_rendered_tiles =
-                tile_viewer.render_screen_tiles(&camera, args.tiles, args.show_culling);
-            tile_viewer.retrieve_tiles_till_out_of_work_or_time(
-                &camera,
-                frame_start_time,
-                frame_time_limit,
-            );
-            num_rendered_tiles
+            tile_viewer.update(&camera, frame_start_time, frame_time_limit);
+            tile_viewer.render(&camera, args.tiles, args.show_culling)
         };
+
+        // show how far along converting the image to tiles is, until it's done
+        if let Some(progress) = &tiling_progress {
+            if !progress.is_finished() {
+                draw_rectangle(0.0, screen_height() - 10.0, screen_width(), 10.0, DARKGRAY);
+                draw_rectangle(
+                    0.0,
+                    screen_height() - 10.0,
+                    screen_width() * progress.fraction(),
+                    10.0,
+                    WHITE,
+                );
+                draw_text(
+                    &("converting to tiles: ".to_owned()
+                        + &progress.tiles_done().to_string()
+                        + "/"
+                        + &progress.tiles_total().to_string()),
+                    20.0,
+                    screen_height() - 20.0,
+                    30.0,
+                    WHITE,
+                );
+            }
+        }
         // draw text in top left corner
         if args.stats {
             let lod = lod_from_zoom(camera.zoom_multiplier, max_lod);
@@ -801,6 +466,23 @@ async fn main() {
                 30.0,
                 WHITE,
             );
+
+            draw_text(
+                &("cache_mb: ".to_owned()
+                    + &(tile_viewer.cache_size_bytes() / (1024 * 1024)).to_string()),
+                20.0,
+                140.0,
+                30.0,
+                WHITE,
+            );
+
+            draw_text(
+                &("failed_tiles: ".to_owned() + &tile_viewer.failed_tile_count().to_string()),
+                20.0,
+                160.0,
+                30.0,
+                WHITE,
+            );
         }
 
         next_frame().await
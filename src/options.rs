@@ -1,8 +1,50 @@
 use std::path::PathBuf;
+use std::str::FromStr;
+use tileview::source::Layout;
+use tileview::tiler::{TileFormat, TilingSettings};
 
+// Running without a subcommand, as `tileview [options] <path>`, is the same as `tileview view [options] <path>`.
 #[derive(Debug, clap::Parser)]
-#[clap(version)]
-pub struct Args {
+#[clap(
+    version,
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    #[clap(flatten)]
+    pub view: Option<ViewArgs>,
+}
+
+impl Cli {
+    /// The subcommand to run, which is `view` if none was given
+    pub fn into_command(self) -> Command {
+        match (self.command, self.view) {
+            (Some(command), _) => command,
+            (None, Some(view)) => Command::View(view),
+            // clap shows the help instead of getting here, as either a subcommand or an image path is required
+            (None, None) => unreachable!("no subcommand or image path"),
+        }
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Open an image or tile set in a window
+    View(ViewArgs),
+    /// Convert an image file to a tile directory, without opening a window
+    Tile(TileArgs),
+    /// Print the LOD count, tile size and tile grid of each LOD of a tile directory
+    Info(InspectArgs),
+    /// Check a tile directory for missing, misshapen, undecodable and stray files, exiting with an error if any are
+    /// found
+    Verify(InspectArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ViewArgs {
     /// Whether to show stats in the top left
     #[clap(long)]
     pub stats: bool,
@@ -15,6 +57,137 @@ pub struct Args {
     #[clap(long)]
     pub show_culling: bool,
 
-    /// The path to the image or tiles to render
+    /// Whether to stop panning once the center of the screen reaches the edge of the image, if its size is known.
+    #[clap(long)]
+    pub clamp_pan: bool,
+
+    /// How to show the image when it opens, centered in the window. One of fit, to fit it to the window, 1:1, to
+    /// show it at its actual size, or center, to keep the zoom based on the LOD count. Falls back to a zoom based on the LOD count if the image's size isn't known.
+    #[clap(long, default_value = "fit")]
+    pub initial_view: InitialView,
+
+    /// How many threads to read and decode tiles on. Defaults to one less than the number of CPU cores.
+    #[clap(long)]
+    pub decode_threads: Option<usize>,
+
+    /// How many megabytes of tile textures to keep cached. Defaults to 512.
+    #[clap(long)]
+    pub cache_mb: Option<usize>,
+
+    /// Whether to view image files by cutting tiles from the decoded image in memory as they come into view, instead
+    /// of converting them to tiles on disk.
+    #[clap(long)]
+    pub lazy: bool,
+
+    #[clap(flatten)]
+    pub tiling: TilingArgs,
+
+    /// A directory to convert image files to tiles in and keep them, instead of the cache or a temporary directory.
+    /// The directory can be viewed later like any other tile directory.
+    #[clap(long, conflicts_with = "lazy")]
+    pub keep_tiles: Option<PathBuf>,
+
+    /// How many megabytes of tiles converted from image files to keep between launches, in the user's cache directory.
+    /// 0 converts images to a temporary directory instead.
+    #[clap(long, default_value_t = 4096)]
+    pub image_cache_mb: u64,
+
+    #[clap(flatten)]
+    pub dir_layout: DirLayoutArgs,
+
+    /// The path to the image or tiles to render. Can be an image file, a tile directory, a Deep Zoom `.dzi` file,
+    /// a `.zip` or `.tar` of a tile directory, a tiled `.tif`, an `.mbtiles` or `.pmtiles` file, or the URL or
+    /// path of an IIIF `info.json`.
     pub image_path: PathBuf,
 }
+
+/// How the image is shown when it opens
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitialView {
+    /// As large as it can be while fitting in the window
+    Fit,
+    /// One image pixel per screen pixel
+    ActualSize,
+    /// At the zoom based on the LOD count
+    Center,
+}
+
+impl FromStr for InitialView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fit" => Ok(InitialView::Fit),
+            "1:1" | "actual" => Ok(InitialView::ActualSize),
+            "center" => Ok(InitialView::Center),
+            _ => Err(format!(
+                "unknown initial view \"{}\", expected fit, 1:1 or center",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TileArgs {
+    #[clap(flatten)]
+    pub tiling: TilingArgs,
+
+    /// The image file to convert
+    pub image_path: PathBuf,
+
+    /// The directory to write the tiles to, as `<lod>/<x>,<y>.<ext>`. Created if it doesn't exist.
+    pub tile_dir: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct InspectArgs {
+    #[clap(flatten)]
+    pub dir_layout: DirLayoutArgs,
+
+    /// Whether to print JSON instead of text
+    #[clap(long)]
+    pub json: bool,
+
+    /// The tile directory to inspect
+    pub tile_dir: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DirLayoutArgs {
+    /// How the levels and rows of a tile directory are numbered. One of tileview, xyz or tms.
+    #[clap(long, default_value = "tileview")]
+    pub layout: Layout,
+
+    /// The path of each tile relative to the tile directory, such as "{z}/{x}/{y}.{ext}".
+    /// "{-y}" in place of "{y}" flips the y axis.
+    /// Defaults to "{lod}/{x},{y}.{ext}" for the tileview layout, and "{z}/{x}/{y}.png" otherwise.
+    #[clap(long)]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TilingArgs {
+    /// The width and height of the tiles image files are converted to, in pixels, from 16 to 4096.
+    #[clap(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(16..=4096))]
+    pub tile_size: u32,
+
+    /// The format image files are converted to tiles in. One of png, jpeg or webp.
+    #[clap(long, default_value = "png")]
+    pub format: TileFormat,
+
+    /// The quality of jpeg and webp tiles, from 1 to 100.
+    #[clap(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+}
+
+impl TilingArgs {
+    /// How image files are converted to tiles
+    pub fn settings(&self) -> TilingSettings {
+        TilingSettings {
+            tile_size: self.tile_size,
+            format: self.format,
+            quality: self.quality,
+        }
+    }
+}
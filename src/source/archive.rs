@@ -0,0 +1,187 @@
+use super::{invalid_data, TileData, TileSource};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+use zip::ZipArchive;
+
+enum Archive {
+    /// Members are stored by their name in the zip's central directory
+    Zip {
+        archive: Mutex<ZipArchive<File>>,
+        members: HashMap<(i32, i32, usize), String>,
+    },
+    /// Members are stored by the offset and size of their data in the tar file
+    Tar {
+        file: Mutex<File>,
+        members: HashMap<(i32, i32, usize), (u64, u64)>,
+    },
+}
+
+/// Tiles stored as `<lod>/<x>,<y>.png` inside a `.zip` or uncompressed `.tar` archive.
+///
+/// The archive's entries are indexed once when it is opened, and tiles are read from it on demand. The tiles may be
+/// inside a top level directory, as they are when a tile directory is archived.
+pub struct ArchiveSource {
+    archive: Archive,
+    tile_size: (u32, u32),
+    max_lod: usize,
+    image_bounds: (u32, u32),
+    /// Columns and rows of tiles in each LOD
+    tile_grids: Vec<(u32, u32)>,
+}
+
+impl ArchiveSource {
+    /// Whether a path is an archive this source can open
+    pub fn is_archive(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case("tar"))
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let is_zip = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+
+        let archive = if is_zip {
+            // the names come from the central directory, so indexing doesn't read any member's header
+            let archive = ZipArchive::new(File::open(path)?)?;
+            let members = archive
+                .file_names()
+                .filter_map(|name| Some((parse_member_path(name)?, name.to_owned())))
+                .collect();
+            Archive::Zip {
+                archive: Mutex::new(archive),
+                members,
+            }
+        } else {
+            let mut members = HashMap::new();
+            for entry in tar::Archive::new(File::open(path)?).entries()? {
+                let entry = entry?;
+                if let Some(tile) = parse_member_path(&entry.path()?.to_string_lossy()) {
+                    members.insert(tile, (entry.raw_file_position(), entry.size()));
+                }
+            }
+            Archive::Tar {
+                file: Mutex::new(File::open(path)?),
+                members,
+            }
+        };
+
+        let mut source = ArchiveSource {
+            archive,
+            tile_size: (0, 0),
+            max_lod: 0,
+            image_bounds: (0, 0),
+            tile_grids: Vec::new(),
+        };
+
+        // find max_lod the same way as for a tile directory, the last of the consecutive LODs from 0
+        let tiles: Vec<(i32, i32, usize)> = match &source.archive {
+            Archive::Zip { members, .. } => members.keys().copied().collect(),
+            Archive::Tar { members, .. } => members.keys().copied().collect(),
+        };
+        let lods: Vec<usize> = tiles.iter().map(|(_, _, lod)| *lod).collect();
+        if !lods.contains(&0) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no tiles in LOD 0"));
+        }
+        while lods.contains(&(source.max_lod + 1)) {
+            source.max_lod += 1;
+        }
+
+        // the tile grid of each LOD reaches as far as its furthest tile
+        source.tile_grids = vec![(0, 0); source.max_lod + 1];
+        for &(x, y, lod) in &tiles {
+            if x >= 0 && y >= 0 && lod <= source.max_lod {
+                let grid = &mut source.tile_grids[lod];
+                *grid = (grid.0.max(x as u32 + 1), grid.1.max(y as u32 + 1));
+            }
+        }
+
+        // get the dimensions of a tile from lod 0, from the top left one as edge tiles may be smaller
+        let first_tile = *tiles
+            .iter()
+            .filter(|(_, _, lod)| *lod == 0)
+            .min_by_key(|(x, y, _)| (*y, *x))
+            .unwrap();
+        source.tile_size = source.member_dimensions(first_tile)?;
+
+        // the edge tiles of lod 0 give the exact size of the image
+        let (columns, rows) = source.tile_grids[0];
+        let edge_width = source
+            .member_dimensions((columns as i32 - 1, 0, 0))
+            .map_or(source.tile_size.0, |(width, _)| width);
+        let edge_height = source
+            .member_dimensions((0, rows as i32 - 1, 0))
+            .map_or(source.tile_size.1, |(_, height)| height);
+        source.image_bounds = (
+            columns.saturating_sub(1) * source.tile_size.0 + edge_width,
+            rows.saturating_sub(1) * source.tile_size.1 + edge_height,
+        );
+
+        Ok(source)
+    }
+
+    /// Reads the dimensions of a tile's image
+    fn member_dimensions(&self, tile: (i32, i32, usize)) -> io::Result<(u32, u32)> {
+        let bytes = self.read_member(tile)?;
+        image::io::Reader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .into_dimensions()
+            .map_err(invalid_data)
+    }
+
+    fn read_member(&self, tile: (i32, i32, usize)) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match &self.archive {
+            Archive::Zip { archive, members } => {
+                let name = members.get(&tile).ok_or(io::ErrorKind::NotFound)?;
+                archive
+                    .lock()
+                    .unwrap()
+                    .by_name(name)?
+                    .read_to_end(&mut bytes)?;
+            }
+            Archive::Tar { file, members } => {
+                let (offset, size) = *members.get(&tile).ok_or(io::ErrorKind::NotFound)?;
+                let mut file = file.lock().unwrap();
+                file.seek(SeekFrom::Start(offset))?;
+                file.by_ref().take(size).read_to_end(&mut bytes)?;
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+impl TileSource for ArchiveSource {
+    fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    fn lod_count(&self) -> usize {
+        self.max_lod + 1
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn tile_grid(&self, lod: usize) -> Option<(u32, u32)> {
+        self.tile_grids.get(lod).copied()
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        Ok(TileData::Encoded(self.read_member((x, y, lod))?))
+    }
+}
+
+/// Parses a member path ending in `<lod>/<x>,<y>.png` into the tile it holds
+fn parse_member_path(path: &str) -> Option<(i32, i32, usize)> {
+    let mut components = path.rsplit('/');
+    let file_name = components.next()?;
+    let lod = components.next()?.parse().ok()?;
+
+    let (x, y) = file_name.strip_suffix(".png")?.split_once(',')?;
+    Some((x.parse().ok()?, y.parse().ok()?, lod))
+}
@@ -0,0 +1,788 @@
+use super::{invalid_data, SlippyGrid, TileData, TileSource, MAX_ZOOM};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How the levels and rows of a tile directory are numbered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// TileView's own `<lod>/<x>,<y>.<ext>`. Level 0 is the full resolution image.
+    TileView,
+    /// Slippy map `{z}/{x}/{y}.png`. Level 0 is the most zoomed out level.
+    Xyz,
+    /// Like `Xyz`, but with the y axis flipped so row 0 is at the bottom.
+    Tms,
+}
+
+impl Layout {
+    /// The path template used when none is given
+    pub fn default_template(&self) -> &'static str {
+        match self {
+            Layout::TileView => "{lod}/{x},{y}.{ext}",
+            Layout::Xyz | Layout::Tms => "{z}/{x}/{y}.png",
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tileview" => Ok(Layout::TileView),
+            "xyz" => Ok(Layout::Xyz),
+            "tms" => Ok(Layout::Tms),
+            _ => Err(format!(
+                "unknown layout \"{}\", expected tileview, xyz or tms",
+                s
+            )),
+        }
+    }
+}
+
+enum TemplatePart {
+    Literal(String),
+    Level,
+    X,
+    Y,
+    /// The row counted from the bottom of the level instead of the top
+    FlippedY,
+    Ext,
+}
+
+/// A tile path relative to the tile directory, such as `{z}/{x}/{y}.{ext}`.
+///
+/// `{lod}` and `{z}` are both replaced by the level number, which the `Layout` decides the meaning of. `{-y}` is the row
+/// counted from the other end of the level to `{y}`, flipping the layout's y axis. `{ext}` is replaced by whatever file
+/// extension the tiles were found to have.
+struct PathTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+/// The values a tile path was generated from
+struct TemplateMatch {
+    level: i64,
+    x: i64,
+    y: i64,
+    ext: String,
+}
+
+impl PathTemplate {
+    fn parse(template: &str) -> io::Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            if let Some(after_brace) = rest.strip_prefix('{') {
+                let end = after_brace
+                    .find('}')
+                    .ok_or_else(|| invalid_data("unclosed { in path template"))?;
+                parts.push(match &after_brace[..end] {
+                    "lod" | "z" => TemplatePart::Level,
+                    "x" => TemplatePart::X,
+                    "y" => TemplatePart::Y,
+                    "-y" => TemplatePart::FlippedY,
+                    "ext" => TemplatePart::Ext,
+                    other => {
+                        return Err(invalid_data(format!(
+                            "unknown placeholder {{{}}} in path template",
+                            other
+                        )))
+                    }
+                });
+                rest = &after_brace[end + 1..];
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                parts.push(TemplatePart::Literal(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        // levels are found by listing the tile directory, so they must be its subdirectories
+        match parts.as_slice() {
+            [TemplatePart::Level, TemplatePart::Literal(separator), ..]
+                if separator.starts_with('/') => {}
+            _ => return Err(invalid_data("path template must start with {lod}/ or {z}/")),
+        }
+
+        Ok(PathTemplate { parts })
+    }
+
+    fn fill(&self, level: i64, x: i64, y: i64, ext: &str) -> String {
+        let mut path = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => path.push_str(literal),
+                TemplatePart::Level => path.push_str(&level.to_string()),
+                TemplatePart::X => path.push_str(&x.to_string()),
+                TemplatePart::Y => path.push_str(&y.to_string()),
+                TemplatePart::FlippedY => path.push_str(&((1 << level) - 1 - y).to_string()),
+                TemplatePart::Ext => path.push_str(ext),
+            }
+        }
+        path
+    }
+
+    /// Extracts the level, x, y and extension from a path relative to the tile directory, using `/` as separator.
+    /// Levels too deep for a `SlippyGrid` to address don't match.
+    fn matches(&self, path: &str) -> Option<TemplateMatch> {
+        let mut found = TemplateMatch {
+            level: 0,
+            x: 0,
+            y: 0,
+            ext: String::new(),
+        };
+
+        let mut rest = path;
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+                TemplatePart::Ext => {
+                    let end = rest
+                        .find(|c: char| !c.is_ascii_alphanumeric())
+                        .unwrap_or(rest.len());
+                    if end == 0 {
+                        return None;
+                    }
+                    found.ext = rest[..end].to_string();
+                    rest = &rest[end..];
+                }
+                number_part => {
+                    let digits_start = usize::from(rest.starts_with('-'));
+                    let end = rest[digits_start..]
+                        .find(|c: char| !c.is_ascii_digit())
+                        .map_or(rest.len(), |end| end + digits_start);
+                    let number: i64 = rest[..end].parse().ok()?;
+                    match number_part {
+                        TemplatePart::Level if (0..=MAX_ZOOM).contains(&number) => {
+                            found.level = number
+                        }
+                        TemplatePart::Level => return None,
+                        TemplatePart::X => found.x = number,
+                        TemplatePart::FlippedY => found.y = (1 << found.level) - 1 - number,
+                        _ => found.y = number,
+                    }
+                    rest = &rest[end..];
+                }
+            }
+        }
+
+        if rest.is_empty() {
+            Some(found)
+        } else {
+            None
+        }
+    }
+}
+
+/// A tile's path, its size, and the size its place in the grid calls for
+type WrongDimensions = (PathBuf, (u32, u32), (u32, u32));
+
+/// What `DirSource::verify()` found in a tile directory. Tiles are keyed by `(x, y, lod)`.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// How many tiles were found at each LOD
+    pub tiles_found: Vec<usize>,
+    /// Tiles inside their LOD's grid that have no file
+    pub missing: Vec<(i32, i32, usize)>,
+    /// Tiles that are neither the size their place in the grid calls for nor the full tile size, with their path,
+    /// their size and the expected size
+    pub wrong_dimensions: Vec<WrongDimensions>,
+    /// Tiles that couldn't be decoded, with their path and the reason why
+    pub undecodable: Vec<(PathBuf, String)>,
+    /// Files and directories that aren't tiles of any LOD
+    pub stray: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    /// Whether nothing was found to be wrong
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty()
+            && self.wrong_dimensions.is_empty()
+            && self.undecodable.is_empty()
+            && self.stray.is_empty()
+    }
+}
+
+/// Tiles stored as one file per tile in a directory, by default as `<lod>/<x>,<y>.<ext>`
+///
+/// The full resolution level is scanned when the directory is opened, to find how big the image is.
+pub struct DirSource {
+    tile_dir: PathBuf,
+    layout: Layout,
+    template: PathTemplate,
+    ext: String,
+    tile_size: (u32, u32),
+    image_bounds: (u32, u32),
+    /// The levels in the directory. For the TileView layout its zoom levels are LODs, and its origin is unused.
+    grid: SlippyGrid,
+}
+
+impl DirSource {
+    pub fn open(tile_dir: &Path) -> io::Result<Self> {
+        DirSource::open_with_layout(tile_dir, Layout::TileView, None)
+    }
+
+    /// Opens a tile directory using the given layout, and optionally a path template other than the layout's default.
+    pub fn open_with_layout(
+        tile_dir: &Path,
+        layout: Layout,
+        template: Option<&str>,
+    ) -> io::Result<Self> {
+        let template = PathTemplate::parse(template.unwrap_or(layout.default_template()))?;
+
+        let (min_level, max_level) = match layout {
+            Layout::TileView => (0, max_lod_in_tile_dir(tile_dir) as i64),
+            Layout::Xyz | Layout::Tms => {
+                let levels = level_dirs(tile_dir)?;
+                match (levels.iter().min(), levels.iter().max()) {
+                    (Some(min), Some(max)) => (*min, *max),
+                    _ => return Err(invalid_data("no zoom level directories found")),
+                }
+            }
+        };
+        let full_res_level = match layout {
+            Layout::TileView => min_level,
+            Layout::Xyz | Layout::Tms => max_level,
+        };
+
+        // get the file extension of a tile from lod 0
+        let ext = first_tile_in(tile_dir, &template, full_res_level)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no tiles in LOD 0"))?
+            .1
+            .ext;
+
+        let mut source = DirSource {
+            tile_dir: tile_dir.to_path_buf(),
+            layout,
+            template,
+            ext,
+            tile_size: (0, 0),
+            image_bounds: (0, 0),
+            grid: SlippyGrid {
+                min_zoom: min_level,
+                max_zoom: max_level,
+                origin: (0, 0),
+            },
+        };
+
+        // Slippy map tiles are numbered across the whole world, so start from the top left of the coarsest level.
+        if layout != Layout::TileView {
+            let mut origin: Option<(i64, i64)> = None;
+            for_each_tile_in(tile_dir, &source.template, min_level, &mut |_, found| {
+                let y = source.xyz_row(found.level, found.y);
+                origin = Some(match origin {
+                    Some((x0, y0)) => (x0.min(found.x), y0.min(y)),
+                    None => (found.x, y),
+                });
+                true
+            })?;
+            source.grid.origin = origin.unwrap_or((0, 0));
+        }
+
+        // The top left tile of the full resolution level is full size, unlike tiles on the right and bottom edges.
+        // Its furthest tiles give the size of the image.
+        let mut full_res_tiles: Vec<((i64, i64), String)> = Vec::new();
+        let mut columns = 0;
+        let mut rows = 0;
+        for_each_tile_in(
+            tile_dir,
+            &source.template,
+            full_res_level,
+            &mut |path, found| {
+                let (x, y) = source.tile_position(&found);
+                full_res_tiles.push(((x, y), path.to_string()));
+                columns = columns.max(x + 1);
+                rows = rows.max(y + 1);
+                true
+            },
+        )?;
+        if full_res_tiles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no tiles in LOD 0"));
+        }
+
+        // if the top left tile can't be read, fall back to the next one, preferring those away from the edges
+        full_res_tiles.sort_by_key(|((x, y), _)| (*x == columns - 1 || *y == rows - 1, *y, *x));
+        let tile_size = full_res_tiles
+            .iter()
+            .find_map(|(_, path)| image::image_dimensions(tile_dir.join(path)).ok())
+            .ok_or_else(|| invalid_data("none of the tiles in LOD 0 can be read"))?;
+        let (columns, rows) = (columns.max(1) as i32, rows.max(1) as i32);
+
+        source.tile_size = tile_size;
+
+        let edge_width = source
+            .tile_path(columns - 1, 0, 0)
+            .and_then(|path| image::image_dimensions(path).ok())
+            .map_or(tile_size.0, |(width, _)| width);
+        let edge_height = source
+            .tile_path(0, rows - 1, 0)
+            .and_then(|path| image::image_dimensions(path).ok())
+            .map_or(tile_size.1, |(_, height)| height);
+        source.image_bounds = (
+            (columns as u32 - 1) * tile_size.0 + edge_width,
+            (rows as u32 - 1) * tile_size.1 + edge_height,
+        );
+
+        Ok(source)
+    }
+
+    /// Checks every file in the tile directory against the tile grids of its LODs, reporting missing tiles, tiles
+    /// with the wrong dimensions, tiles that can't be decoded and files that aren't tiles.
+    ///
+    /// Every tile is decoded, so this reads the whole directory.
+    pub fn verify(&self) -> io::Result<VerifyReport> {
+        let mut report = VerifyReport {
+            tiles_found: vec![0; self.lod_count()],
+            ..VerifyReport::default()
+        };
+        let mut found = HashSet::new();
+
+        for entry in fs::read_dir(&self.tile_dir)? {
+            let entry = entry?;
+            let level = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<i64>().ok())
+                .filter(|level| (self.grid.min_zoom..=self.grid.max_zoom).contains(level));
+            match level {
+                Some(level) if entry.file_type()?.is_dir() => {
+                    let mut paths = Vec::new();
+                    list_files(&self.tile_dir, level.to_string(), &mut paths)?;
+                    for path in paths {
+                        self.verify_file(&path, &mut report, &mut found);
+                    }
+                }
+                _ => report.stray.push(entry.path()),
+            }
+        }
+
+        for lod in 0..self.lod_count() {
+            let (columns, rows) = self.tile_grid(lod).unwrap_or((0, 0));
+            for y in 0..rows as i32 {
+                for x in 0..columns as i32 {
+                    if !found.contains(&(x, y, lod)) {
+                        report.missing.push((x, y, lod));
+                    }
+                }
+            }
+        }
+
+        report.wrong_dimensions.sort();
+        report.undecodable.sort();
+        report.stray.sort();
+        Ok(report)
+    }
+
+    /// Checks a single file found under a level directory, given its path relative to the tile directory
+    fn verify_file(
+        &self,
+        path: &str,
+        report: &mut VerifyReport,
+        found: &mut HashSet<(i32, i32, usize)>,
+    ) {
+        let full_path = self.tile_dir.join(path);
+        let tile = self
+            .template
+            .matches(path)
+            .filter(|found| found.ext == self.ext)
+            .and_then(|found| {
+                let lod = match self.layout {
+                    Layout::TileView => found.level as usize,
+                    Layout::Xyz | Layout::Tms => (self.grid.max_zoom - found.level) as usize,
+                };
+                let (x, y) = self.tile_position(&found);
+                let (columns, rows) = self.tile_grid(lod)?;
+                (x >= 0 && y >= 0 && x < columns as i64 && y < rows as i64)
+                    .then_some((x as i32, y as i32, lod))
+            });
+        let Some((x, y, lod)) = tile else {
+            report.stray.push(full_path);
+            return;
+        };
+
+        found.insert((x, y, lod));
+        report.tiles_found[lod] += 1;
+
+        match image::open(&full_path) {
+            Ok(image) => {
+                // tiles on the right and bottom edges may be cut to the image, or padded to the full tile size
+                let size = (image.width(), image.height());
+                let expected = self.expected_tile_size(x, y, lod);
+                if size != expected && size != self.tile_size {
+                    report.wrong_dimensions.push((full_path, size, expected));
+                }
+            }
+            Err(error) => report.undecodable.push((full_path, error.to_string())),
+        }
+    }
+
+    /// The size of a tile cut from the image at its LOD, which is smaller than the tile size on the right and bottom
+    /// edges of the image.
+    fn expected_tile_size(&self, x: i32, y: i32, lod: usize) -> (u32, u32) {
+        let scale = 1u64 << lod.min(32);
+        let lod_width = (self.image_bounds.0 as u64).div_ceil(scale) as u32;
+        let lod_height = (self.image_bounds.1 as u64).div_ceil(scale) as u32;
+        (
+            self.tile_size
+                .0
+                .min(lod_width.saturating_sub(x as u32 * self.tile_size.0)),
+            self.tile_size
+                .1
+                .min(lod_height.saturating_sub(y as u32 * self.tile_size.1)),
+        )
+    }
+
+    /// The position of a tile as the viewer numbers it, from the values its path was generated from
+    fn tile_position(&self, found: &TemplateMatch) -> (i64, i64) {
+        match self.layout {
+            Layout::TileView => (found.x, found.y),
+            Layout::Xyz | Layout::Tms => {
+                let shift = found.level - self.grid.min_zoom;
+                (
+                    found.x - (self.grid.origin.0 << shift),
+                    self.xyz_row(found.level, found.y) - (self.grid.origin.1 << shift),
+                )
+            }
+        }
+    }
+
+    /// Converts between XYZ and TMS row numbering, which is the same conversion in both directions.
+    fn xyz_row(&self, level: i64, y: i64) -> i64 {
+        match self.layout {
+            Layout::Tms => (1 << level) - 1 - y,
+            Layout::TileView | Layout::Xyz => y,
+        }
+    }
+
+    fn tile_path(&self, x: i32, y: i32, lod: usize) -> Option<PathBuf> {
+        let (level, x, y) = match self.layout {
+            Layout::TileView => (lod as i64, x as i64, y as i64),
+            Layout::Xyz | Layout::Tms => {
+                let (level, x, y) = self.grid.tile(x, y, lod)?;
+                (level, x, self.xyz_row(level, y))
+            }
+        };
+
+        Some(
+            self.tile_dir
+                .join(self.template.fill(level, x, y, &self.ext)),
+        )
+    }
+}
+
+impl TileSource for DirSource {
+    fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    fn lod_count(&self) -> usize {
+        self.grid.lod_count()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let path = self.tile_path(x, y, lod).ok_or(io::ErrorKind::NotFound)?;
+        Ok(TileData::Encoded(fs::read(path)?))
+    }
+}
+
+// finds max_lod in a directory containing tile lods
+fn max_lod_in_tile_dir(dir: &Path) -> usize {
+    let mut max_lod: usize = 0;
+    for x in 0.. {
+        if dir.to_path_buf().join(x.to_string()).is_dir() {
+            max_lod = x;
+        } else {
+            break;
+        }
+    }
+    max_lod
+}
+
+/// Lists the numbered level directories in a tile directory, skipping numbers too deep to be zoom levels such as years
+fn level_dirs(dir: &Path) -> io::Result<Vec<i64>> {
+    let mut levels = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Some(level) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+                .filter(|level| (0..=MAX_ZOOM).contains(level))
+            {
+                levels.push(level);
+            }
+        }
+    }
+    Ok(levels)
+}
+
+/// Lists the relative paths of every file under a directory relative to the tile directory, using `/` as separator.
+fn list_files(tile_dir: &Path, relative: String, paths: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(tile_dir.join(&relative))? {
+        let entry = entry?;
+        let path = relative.clone() + "/" + &entry.file_name().to_string_lossy();
+        if entry.file_type()?.is_dir() {
+            list_files(tile_dir, path, paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` with the relative path of every tile in a level, until `f` returns false.
+fn for_each_tile_in(
+    tile_dir: &Path,
+    template: &PathTemplate,
+    level: i64,
+    f: &mut dyn FnMut(&str, TemplateMatch) -> bool,
+) -> io::Result<()> {
+    fn visit(
+        tile_dir: &Path,
+        relative: String,
+        template: &PathTemplate,
+        f: &mut dyn FnMut(&str, TemplateMatch) -> bool,
+    ) -> io::Result<bool> {
+        for entry in fs::read_dir(tile_dir.join(&relative))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = relative.clone() + "/" + &name;
+            if entry.file_type()?.is_dir() {
+                if !visit(tile_dir, path, template, f)? {
+                    return Ok(false);
+                }
+            } else if let Some(found) = template.matches(&path) {
+                if !f(&path, found) {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    visit(tile_dir, level.to_string(), template, f)?;
+    Ok(())
+}
+
+/// Finds any one tile in a level
+fn first_tile_in(
+    tile_dir: &Path,
+    template: &PathTemplate,
+    level: i64,
+) -> io::Result<Option<(String, TemplateMatch)>> {
+    let mut first = None;
+    for_each_tile_in(tile_dir, template, level, &mut |path, found| {
+        first = Some((path.to_string(), found));
+        false
+    })?;
+    Ok(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The level, x, y and extension a path matches, for comparing
+    fn matched(template: &PathTemplate, path: &str) -> Option<(i64, i64, i64, String)> {
+        template
+            .matches(path)
+            .map(|found| (found.level, found.x, found.y, found.ext))
+    }
+
+    #[test]
+    fn slippy_map_paths_are_filled_and_matched() {
+        let template = PathTemplate::parse("{z}/{x}/{y}.{ext}").unwrap();
+        assert_eq!(template.fill(3, 5, 2, "png"), "3/5/2.png");
+        assert_eq!(
+            matched(&template, "3/5/2.png"),
+            Some((3, 5, 2, "png".to_string()))
+        );
+        assert_eq!(matched(&template, "3/5.png"), None);
+        assert_eq!(matched(&template, "3/5/2"), None);
+    }
+
+    #[test]
+    fn flipped_rows_count_from_the_bottom() {
+        let template = PathTemplate::parse("{z}/{x}/{-y}.png").unwrap();
+        assert_eq!(template.fill(2, 1, 0, "png"), "2/1/3.png");
+        assert_eq!(
+            matched(&template, "2/1/3.png"),
+            Some((2, 1, 0, String::new()))
+        );
+        assert_eq!(
+            matched(&template, "0/0/0.png"),
+            Some((0, 0, 0, String::new()))
+        );
+    }
+
+    #[test]
+    fn literals_around_placeholders_must_match() {
+        let template = PathTemplate::parse("{lod}/tile_{x}-{y}@2x.{ext}").unwrap();
+        assert_eq!(template.fill(1, 4, 7, "jpg"), "1/tile_4-7@2x.jpg");
+        assert_eq!(
+            matched(&template, "1/tile_4-7@2x.jpg"),
+            Some((1, 4, 7, "jpg".to_string()))
+        );
+        assert_eq!(
+            matched(&template, "1/tile_-4--7@2x.jpg"),
+            Some((1, -4, -7, "jpg".to_string()))
+        );
+        assert_eq!(matched(&template, "1/tile_4-7.jpg"), None);
+        assert_eq!(matched(&template, "1/4-7@2x.jpg"), None);
+    }
+
+    #[test]
+    fn partially_written_tiles_are_not_matched() {
+        let template = PathTemplate::parse("{lod}/{x},{y}.{ext}").unwrap();
+        assert_eq!(
+            matched(&template, "0/1,2.png"),
+            Some((0, 1, 2, "png".to_string()))
+        );
+        assert_eq!(matched(&template, "0/1,2.png.partial"), None);
+    }
+
+    #[test]
+    fn levels_too_deep_to_be_zoom_levels_are_not_matched() {
+        let template = PathTemplate::parse("{z}/{x}/{-y}.png").unwrap();
+        assert_eq!(matched(&template, "2024/0/0.png"), None);
+        assert_eq!(matched(&template, "-1/0/0.png"), None);
+    }
+
+    #[test]
+    fn only_zoom_level_directories_are_listed() {
+        let tile_dir = tempdir::TempDir::new("level-dirs-test").unwrap();
+        for name in ["0", "1", "2024", "-1", "tiles"] {
+            fs::create_dir(tile_dir.path().join(name)).unwrap();
+        }
+        let mut levels = level_dirs(tile_dir.path()).unwrap();
+        levels.sort();
+        assert_eq!(levels, [0, 1]);
+    }
+
+    /// A 6x6 image cut into 4x4 tiles, so LOD 0 has 2x2 tiles with smaller ones on the right and bottom edges, and
+    /// LOD 1 a single 3x3 tile
+    fn consistent_tile_dir() -> tempdir::TempDir {
+        let tile_dir = tempdir::TempDir::new("verify-test").unwrap();
+        let tiles = [
+            (0, 0, 0, (4, 4)),
+            (1, 0, 0, (2, 4)),
+            (0, 1, 0, (4, 2)),
+            (1, 1, 0, (2, 2)),
+            (0, 0, 1, (3, 3)),
+        ];
+        for (x, y, lod, (width, height)) in tiles {
+            let lod_dir = tile_dir.path().join(lod.to_string());
+            fs::create_dir_all(&lod_dir).unwrap();
+            image::RgbaImage::new(width, height)
+                .save(lod_dir.join(format!("{},{}.png", x, y)))
+                .unwrap();
+        }
+        tile_dir
+    }
+
+    fn verify(tile_dir: &tempdir::TempDir) -> VerifyReport {
+        DirSource::open(tile_dir.path()).unwrap().verify().unwrap()
+    }
+
+    #[test]
+    fn consistent_directories_pass() {
+        let tile_dir = consistent_tile_dir();
+        let report = verify(&tile_dir);
+        assert!(report.is_consistent(), "{:?}", report);
+        assert_eq!(report.tiles_found, [4, 1]);
+    }
+
+    #[test]
+    fn missing_tiles_are_reported() {
+        let tile_dir = consistent_tile_dir();
+        fs::remove_file(tile_dir.path().join("0/1,1.png")).unwrap();
+
+        let report = verify(&tile_dir);
+        assert_eq!(report.missing, [(1, 1, 0)]);
+        assert_eq!(report.tiles_found, [3, 1]);
+        assert!(report.wrong_dimensions.is_empty() && report.undecodable.is_empty());
+        assert!(report.stray.is_empty());
+    }
+
+    #[test]
+    fn tiles_with_wrong_dimensions_are_reported() {
+        let tile_dir = consistent_tile_dir();
+        let path = tile_dir.path().join("0/1,1.png");
+        image::RgbaImage::new(3, 3).save(&path).unwrap();
+
+        let report = verify(&tile_dir);
+        assert_eq!(report.wrong_dimensions, [(path, (3, 3), (2, 2))]);
+        assert!(report.missing.is_empty() && report.undecodable.is_empty());
+        assert!(report.stray.is_empty());
+    }
+
+    #[test]
+    fn edge_tiles_padded_to_the_full_tile_size_are_accepted() {
+        let tile_dir = consistent_tile_dir();
+        image::RgbaImage::new(4, 4)
+            .save(tile_dir.path().join("0/1,1.png"))
+            .unwrap();
+
+        assert!(verify(&tile_dir).is_consistent());
+    }
+
+    #[test]
+    fn undecodable_tiles_are_reported() {
+        let tile_dir = consistent_tile_dir();
+        let path = tile_dir.path().join("0/1,1.png");
+        fs::write(&path, b"not a png").unwrap();
+
+        let report = verify(&tile_dir);
+        assert_eq!(report.undecodable.len(), 1);
+        assert_eq!(report.undecodable[0].0, path);
+        assert_eq!(report.tiles_found, [4, 1]);
+        assert!(report.missing.is_empty() && report.wrong_dimensions.is_empty());
+        assert!(report.stray.is_empty());
+    }
+
+    #[test]
+    fn an_undecodable_top_left_tile_is_reported_rather_than_failing_to_open() {
+        let tile_dir = consistent_tile_dir();
+        let path = tile_dir.path().join("0/0,0.png");
+        fs::write(&path, b"not a png").unwrap();
+
+        let report = verify(&tile_dir);
+        assert_eq!(report.undecodable.len(), 1);
+        assert_eq!(report.undecodable[0].0, path);
+    }
+
+    #[test]
+    fn stray_files_are_reported() {
+        let tile_dir = consistent_tile_dir();
+        fs::write(tile_dir.path().join("notes.txt"), b"").unwrap();
+        fs::write(tile_dir.path().join("0/1,1.png.partial"), b"").unwrap();
+        fs::write(tile_dir.path().join("1/5,5.png"), b"").unwrap();
+
+        let report = verify(&tile_dir);
+        assert_eq!(
+            report.stray,
+            [
+                tile_dir.path().join("0/1,1.png.partial"),
+                tile_dir.path().join("1/5,5.png"),
+                tile_dir.path().join("notes.txt"),
+            ]
+        );
+        assert!(report.missing.is_empty() && report.wrong_dimensions.is_empty());
+        assert!(report.undecodable.is_empty());
+    }
+
+    #[test]
+    fn templates_must_start_with_a_level_directory() {
+        assert!(PathTemplate::parse("{x}/{y}/{z}.png").is_err());
+        assert!(PathTemplate::parse("{z}_{x}_{y}.png").is_err());
+        assert!(PathTemplate::parse("{z}/{x}/{w}.png").is_err());
+        assert!(PathTemplate::parse("{z}/{x}/{y.png").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use super::{invalid_data, TileData, TileSource};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A Deep Zoom image. An XML `.dzi` descriptor next to a `<name>_files/<level>/<col>_<row>.<format>` pyramid.
+///
+/// Deep Zoom numbers its levels from the smallest (a single pixel) to the largest, so the full resolution level
+/// is LOD 0 here and every level below it is one LOD higher.
+pub struct DziSource {
+    files_dir: PathBuf,
+    format: String,
+    tile_size: u32,
+    overlap: u32,
+    image_bounds: (u32, u32),
+    max_level: usize,
+}
+
+impl DziSource {
+    pub fn open(dzi_path: &Path) -> io::Result<Self> {
+        let xml = fs::read_to_string(dzi_path)?;
+        let doc = roxmltree::Document::parse(&xml).map_err(invalid_data)?;
+
+        let image = doc.root_element();
+        let size = image
+            .children()
+            .find(|node| node.has_tag_name("Size"))
+            .ok_or_else(|| invalid_data("missing Size element"))?;
+
+        let tile_size = parse_attribute(image, "TileSize")?;
+        let overlap = parse_attribute(image, "Overlap")?;
+        let format = image
+            .attribute("Format")
+            .ok_or_else(|| invalid_data("missing Format attribute"))?
+            .to_string();
+        let width: u32 = parse_attribute(size, "Width")?;
+        let height: u32 = parse_attribute(size, "Height")?;
+
+        // the largest level is the first one whose size is at least that of the image
+        let mut max_level = 0;
+        while (1u64 << max_level) < width.max(height) as u64 {
+            max_level += 1;
+        }
+
+        // tiles live in "<name>_files" next to "<name>.dzi"
+        let stem = dzi_path
+            .file_stem()
+            .ok_or_else(|| invalid_data("invalid .dzi path"))?
+            .to_string_lossy();
+        let files_dir = dzi_path.with_file_name(stem.to_string() + "_files");
+
+        Ok(DziSource {
+            files_dir,
+            format,
+            tile_size,
+            overlap,
+            image_bounds: (width, height),
+            max_level,
+        })
+    }
+}
+
+impl TileSource for DziSource {
+    fn tile_size(&self) -> (u32, u32) {
+        (self.tile_size, self.tile_size)
+    }
+
+    fn lod_count(&self) -> usize {
+        self.max_level + 1
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn tile_overlap(&self) -> u32 {
+        self.overlap
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        if x < 0 || y < 0 || lod > self.max_level {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        let level = self.max_level - lod;
+        let path = self
+            .files_dir
+            .join(level.to_string())
+            .join(format!("{}_{}.{}", x, y, self.format));
+
+        Ok(TileData::Encoded(fs::read(path)?))
+    }
+}
+
+fn parse_attribute<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> io::Result<T> {
+    node.attribute(name)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid_data(format!("missing or invalid {} attribute", name)))
+}
@@ -0,0 +1,161 @@
+use super::{invalid_data, TileData, TileSource};
+use std::fs;
+use std::io::{self, Read};
+use std::time::Duration;
+
+/// How long to wait for an IIIF server to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for an IIIF server to send more of a response, so a stalled server can't hold on to a decode
+/// worker forever
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An image served by an IIIF Image API server, described by its `info.json`.
+///
+/// Each of the server's power of two scale factors becomes a LOD, with scale factor 1 being LOD 0.
+pub struct IiifSource {
+    agent: ureq::Agent,
+    /// The image's base URI, which tile requests are appended to
+    id: String,
+    tile_size: (u32, u32),
+    image_bounds: (u32, u32),
+    /// Whether the server offers each LOD
+    lods: Vec<bool>,
+}
+
+impl IiifSource {
+    /// Opens an `info.json`, given either its URL or a path to a local copy.
+    pub fn open(location: &str) -> io::Result<Self> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(READ_TIMEOUT)
+            .build();
+        let info = if is_url(location) {
+            fetch(&agent, location)?
+        } else {
+            fs::read(location)?
+        };
+        let info: serde_json::Value = serde_json::from_slice(&info).map_err(invalid_data)?;
+
+        // Image API 3 calls it "id", earlier versions "@id"
+        let id = info
+            .get("id")
+            .or_else(|| info.get("@id"))
+            .and_then(|id| id.as_str())
+            .ok_or_else(|| invalid_data("info.json has no id"))?
+            .trim_end_matches('/')
+            .to_string();
+
+        let dimension = |value: &serde_json::Value, name: &str| {
+            value
+                .get(name)
+                .and_then(|value| value.as_u64())
+                .map(|value| value as u32)
+        };
+        let width =
+            dimension(&info, "width").ok_or_else(|| invalid_data("info.json has no width"))?;
+        let height =
+            dimension(&info, "height").ok_or_else(|| invalid_data("info.json has no height"))?;
+
+        let tiles = info
+            .get("tiles")
+            .and_then(|tiles| tiles.get(0))
+            .ok_or_else(|| invalid_data("info.json has no tiles"))?;
+        let tile_width = dimension(tiles, "width")
+            .ok_or_else(|| invalid_data("info.json tiles have no width"))?;
+        let tile_height = dimension(tiles, "height").unwrap_or(tile_width);
+
+        let mut lods = Vec::new();
+        for scale_factor in tiles
+            .get("scaleFactors")
+            .and_then(|factors| factors.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|factor| factor.as_u64())
+        {
+            // only power of two scale factors line up with LODs
+            if scale_factor.is_power_of_two() {
+                let lod = scale_factor.trailing_zeros() as usize;
+                if lods.len() <= lod {
+                    lods.resize(lod + 1, false);
+                }
+                lods[lod] = true;
+            }
+        }
+        if lods.is_empty() {
+            return Err(invalid_data("info.json has no usable scaleFactors"));
+        }
+
+        Ok(IiifSource {
+            agent,
+            id,
+            tile_size: (tile_width, tile_height),
+            image_bounds: (width, height),
+            lods,
+        })
+    }
+}
+
+impl TileSource for IiifSource {
+    fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    fn lod_count(&self) -> usize {
+        self.lods.len()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        if x < 0 || y < 0 || !self.lods.get(lod).copied().unwrap_or(false) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        // the region of the full resolution image this tile covers
+        let scale_factor = 1u64 << lod;
+        let region_x = x as u64 * self.tile_size.0 as u64 * scale_factor;
+        let region_y = y as u64 * self.tile_size.1 as u64 * scale_factor;
+        if region_x >= self.image_bounds.0 as u64 || region_y >= self.image_bounds.1 as u64 {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let region_width =
+            (self.tile_size.0 as u64 * scale_factor).min(self.image_bounds.0 as u64 - region_x);
+        let region_height =
+            (self.tile_size.1 as u64 * scale_factor).min(self.image_bounds.1 as u64 - region_y);
+
+        let url = format!(
+            "{}/{},{},{},{}/{},/0/default.jpg",
+            self.id,
+            region_x,
+            region_y,
+            region_width,
+            region_height,
+            region_width.div_ceil(scale_factor),
+        );
+
+        Ok(TileData::Encoded(fetch(&self.agent, &url)?))
+    }
+}
+
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Whether a path or URL given on the command line is of an IIIF `info.json`
+pub fn is_iiif_location(location: &str) -> bool {
+    location.ends_with("info.json")
+}
+
+fn fetch(agent: &ureq::Agent, url: &str) -> io::Result<Vec<u8>> {
+    let response = agent.get(url).call().map_err(|error| match error {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, error.to_string()),
+        error => io::Error::other(error.to_string()),
+    })?;
+
+    let mut bytes = Vec::new();
+    response.into_reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,151 @@
+use super::{invalid_data, lod_count_for, TileData, TileSource};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// How many bytes of cut tiles a `LazyImageSource` keeps before dropping the least recently read ones
+const TILE_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// An image file decoded into memory, cut into tiles only as they are read.
+///
+/// Nothing is ever written to disk. Each LOD is made the first time one of its tiles is read, and kept. The coarsest
+/// LOD, which a viewer reads first, is shrunk straight from the decoded image, and every other LOD is halved from the
+/// one below it, making it first on the way if need be. That work happens on whichever thread reads the tile, so the
+/// first tile read from a LOD takes as long as making it. Cut tiles are cached up to `TILE_CACHE_BYTES`, so reading
+/// one again is cheap.
+pub struct LazyImageSource {
+    tile_size: u32,
+    image_bounds: (u32, u32),
+    /// The image at each LOD, with LOD 0 being the decoded image
+    lod_images: Vec<OnceLock<RgbaImage>>,
+    tiles: Mutex<TileCache>,
+}
+
+impl LazyImageSource {
+    /// Decodes an image file, which is then tiled with tiles of `tile_size` pixels.
+    pub fn open(path: &Path, tile_size: u32) -> io::Result<Self> {
+        let image = image::open(path).map_err(invalid_data)?.to_rgba8();
+        Ok(LazyImageSource::from_image(image, tile_size))
+    }
+
+    pub fn from_image(image: RgbaImage, tile_size: u32) -> Self {
+        let image_bounds = image.dimensions();
+        let mut lod_images = vec![OnceLock::from(image)];
+        lod_images.resize_with(lod_count_for(image_bounds, tile_size), OnceLock::new);
+
+        LazyImageSource {
+            tile_size,
+            image_bounds,
+            lod_images,
+            tiles: Mutex::new(TileCache::default()),
+        }
+    }
+
+    /// The image at a LOD, making it if that hasn't been done yet
+    fn lod_image(&self, lod: usize) -> &RgbaImage {
+        self.lod_images[lod].get_or_init(|| {
+            if lod == self.lod_images.len() - 1 {
+                // halving an image n times rounds its size up to the same as dividing it by 2^n once
+                let image = self.lod_image(0);
+                let scale = 1 << lod;
+                imageops::thumbnail(
+                    image,
+                    image.width().div_ceil(scale),
+                    image.height().div_ceil(scale),
+                )
+            } else {
+                let below = self.lod_image(lod - 1);
+                imageops::resize(
+                    below,
+                    below.width().div_ceil(2),
+                    below.height().div_ceil(2),
+                    FilterType::Triangle,
+                )
+            }
+        })
+    }
+}
+
+impl TileSource for LazyImageSource {
+    fn tile_size(&self) -> (u32, u32) {
+        (self.tile_size, self.tile_size)
+    }
+
+    fn lod_count(&self) -> usize {
+        self.lod_images.len()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let (columns, rows) = self.tile_grid(lod).ok_or(io::ErrorKind::NotFound)?;
+        if lod >= self.lod_images.len() || x < 0 || y < 0 || x as u32 >= columns || y as u32 >= rows
+        {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        if let Some(tile) = self.tiles.lock().unwrap().get((x, y, lod)) {
+            return Ok(TileData::Decoded(tile));
+        }
+
+        let lod_image = self.lod_image(lod);
+        let left = x as u32 * self.tile_size;
+        let top = y as u32 * self.tile_size;
+        let tile = imageops::crop_imm(
+            lod_image,
+            left,
+            top,
+            self.tile_size.min(lod_image.width() - left),
+            self.tile_size.min(lod_image.height() - top),
+        )
+        .to_image();
+
+        self.tiles.lock().unwrap().insert((x, y, lod), tile.clone());
+        Ok(TileData::Decoded(tile))
+    }
+}
+
+/// Tiles already cut from their LOD images, dropping the least recently read ones once over `TILE_CACHE_BYTES`
+#[derive(Default)]
+struct TileCache {
+    /// Each tile, with the read it was last used by
+    tiles: HashMap<(i32, i32, usize), (RgbaImage, u64)>,
+    /// The tiles by the read they were last used by, least recent first
+    by_last_read: BTreeMap<u64, (i32, i32, usize)>,
+    bytes: usize,
+    reads: u64,
+}
+
+impl TileCache {
+    fn get(&mut self, tile: (i32, i32, usize)) -> Option<RgbaImage> {
+        self.reads += 1;
+        let (image, last_read) = self.tiles.get_mut(&tile)?;
+        self.by_last_read.remove(last_read);
+        *last_read = self.reads;
+        self.by_last_read.insert(self.reads, tile);
+        Some(image.clone())
+    }
+
+    fn insert(&mut self, tile: (i32, i32, usize), image: RgbaImage) {
+        self.reads += 1;
+        self.bytes += image.as_raw().len();
+        if let Some((replaced, last_read)) = self.tiles.insert(tile, (image, self.reads)) {
+            self.bytes -= replaced.as_raw().len();
+            self.by_last_read.remove(&last_read);
+        }
+        self.by_last_read.insert(self.reads, tile);
+
+        while self.bytes > TILE_CACHE_BYTES {
+            let Some((_, evicted)) = self.by_last_read.pop_first() else {
+                break;
+            };
+            let (image, _) = self.tiles.remove(&evicted).unwrap();
+            self.bytes -= image.as_raw().len();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use super::{check_zoom_range, invalid_data, SlippyGrid, TileData, TileSource};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Tiles stored in an MBTiles SQLite database.
+///
+/// MBTiles numbers its rows like TMS, so row 0 is at the bottom of the world.
+pub struct MbTilesSource {
+    connection: Mutex<Connection>,
+    tile_size: (u32, u32),
+    image_bounds: Option<(u32, u32)>,
+    grid: SlippyGrid,
+}
+
+impl MbTilesSource {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(sql_error)?;
+
+        // prefer the zoom range in the metadata, but fall back to the tiles actually present if it names an empty level
+        let (min_zoom, max_zoom) = {
+            let (tiles_min, tiles_max): (Option<i64>, Option<i64>) = connection
+                .query_row(
+                    "SELECT MIN(zoom_level), MAX(zoom_level) FROM tiles",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .map_err(sql_error)?;
+            let min_zoom = metadata_zoom(&connection, "minzoom")?.or(tiles_min);
+            let max_zoom = metadata_zoom(&connection, "maxzoom")?.or(tiles_max);
+            match (min_zoom, max_zoom) {
+                (Some(min_zoom), Some(max_zoom)) => (min_zoom, max_zoom),
+                _ => return Err(invalid_data("MBTiles file contains no tiles")),
+            }
+        };
+        check_zoom_range(min_zoom, max_zoom)?;
+
+        // the top left tile of the most zoomed out level. The largest TMS row is the top one.
+        let origin = {
+            let (x, tms_y): (Option<i64>, Option<i64>) = connection
+                .query_row(
+                    "SELECT MIN(tile_column), MAX(tile_row) FROM tiles WHERE zoom_level = ?1",
+                    [min_zoom],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .map_err(sql_error)?;
+            match (x, tms_y) {
+                (Some(x), Some(tms_y)) => (x, (1 << min_zoom) - 1 - tms_y),
+                _ => (0, 0),
+            }
+        };
+
+        // get the dimensions of a tile from lod 0
+        let tile_size = {
+            let tile_data: Vec<u8> = connection
+                .query_row(
+                    "SELECT tile_data FROM tiles WHERE zoom_level = ?1 LIMIT 1",
+                    [max_zoom],
+                    |row| row.get(0),
+                )
+                .map_err(sql_error)?;
+            let tile = image::load_from_memory(&tile_data).map_err(invalid_data)?;
+            (tile.width(), tile.height())
+        };
+
+        let grid = SlippyGrid {
+            min_zoom,
+            max_zoom,
+            origin,
+        };
+
+        // bounds are stored as "left,bottom,right,top" in degrees
+        let image_bounds = metadata(&connection, "bounds")?.and_then(|bounds| {
+            let bounds: Vec<f64> = bounds
+                .split(',')
+                .filter_map(|value| value.trim().parse().ok())
+                .collect();
+            match bounds.as_slice() {
+                [_, bottom, right, _] => Some(grid.pixels_to(*right, *bottom, tile_size)),
+                _ => None,
+            }
+        });
+
+        Ok(MbTilesSource {
+            connection: Mutex::new(connection),
+            tile_size,
+            image_bounds,
+            grid,
+        })
+    }
+}
+
+impl TileSource for MbTilesSource {
+    fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    fn lod_count(&self) -> usize {
+        self.grid.lod_count()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        self.image_bounds
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let (z, x, y) = self.grid.tile(x, y, lod).ok_or(io::ErrorKind::NotFound)?;
+        let tms_y = (1 << z) - 1 - y;
+
+        let tile_data: Option<Vec<u8>> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                [z, x, tms_y],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+
+        Ok(TileData::Encoded(tile_data.ok_or(io::ErrorKind::NotFound)?))
+    }
+}
+
+/// Reads a value from the MBTiles metadata table
+fn metadata(connection: &Connection, name: &str) -> io::Result<Option<String>> {
+    connection
+        .query_row(
+            "SELECT value FROM metadata WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(sql_error)
+}
+
+/// Reads a zoom level from the MBTiles metadata table, if it names a level that has tiles
+fn metadata_zoom(connection: &Connection, name: &str) -> io::Result<Option<i64>> {
+    let Some(zoom) = metadata(connection, name)?.and_then(|value| value.parse().ok()) else {
+        return Ok(None);
+    };
+    let has_tiles: bool = connection
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM tiles WHERE zoom_level = ?1)",
+            [zoom],
+            |row| row.get(0),
+        )
+        .map_err(sql_error)?;
+    Ok(has_tiles.then_some(zoom))
+}
+
+fn sql_error(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
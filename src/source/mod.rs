@@ -0,0 +1,212 @@
+use image::RgbaImage;
+use std::io;
+
+mod archive;
+mod dir;
+mod dzi;
+mod iiif;
+mod lazy;
+mod mbtiles;
+mod pmtiles;
+mod tiff;
+mod zoomify;
+pub use archive::ArchiveSource;
+pub use dir::{DirSource, Layout, VerifyReport};
+pub use dzi::DziSource;
+pub use iiif::{is_iiif_location, IiifSource};
+pub use lazy::LazyImageSource;
+pub use mbtiles::MbTilesSource;
+pub use pmtiles::{FileRangeReader, PmTilesSource, RangeReader};
+pub use tiff::TiffSource;
+pub use zoomify::ZoomifySource;
+
+/// A single tile as returned by a `TileSource`
+pub enum TileData {
+    /// Encoded image bytes (PNG, JPEG, ...) that still need to be decoded
+    Encoded(Vec<u8>),
+    /// Already decoded pixels
+    Decoded(RgbaImage),
+}
+
+impl TileData {
+    /// Decodes the tile into RGBA pixels, if it is not decoded already.
+    pub fn into_rgba(self) -> image::ImageResult<RgbaImage> {
+        match self {
+            TileData::Encoded(bytes) => Ok(image::load_from_memory(&bytes)?.to_rgba8()),
+            TileData::Decoded(image) => Ok(image),
+        }
+    }
+}
+
+/// Something that tiles of an image can be retrieved from.
+///
+/// Tiles are addressed by `(x, y, lod)`. LOD 0 is the full resolution image, and each LOD above it has half the
+/// resolution of the one below. So a tile in LOD `n` covers `tile_size * 2^n` pixels of the full resolution image.
+pub trait TileSource: Send + Sync {
+    /// The dimensions of a tile in pixels
+    fn tile_size(&self) -> (u32, u32);
+
+    /// How many LODs the source has. LODs range from 0 to `lod_count() - 1`.
+    fn lod_count(&self) -> usize;
+
+    /// The dimensions of the full resolution image in pixels, if known.
+    fn image_bounds(&self) -> Option<(u32, u32)>;
+
+    /// How many columns and rows of tiles a LOD has, starting from tile (0, 0), if known. No tiles exist outside them.
+    ///
+    /// Defaults to the tiles needed to cover `image_bounds()` at the LOD.
+    fn tile_grid(&self, lod: usize) -> Option<(u32, u32)> {
+        let (width, height) = self.image_bounds()?;
+        let (tile_width, tile_height) = self.tile_size();
+        let scale = 1u64 << lod.min(32);
+        Some((
+            (width as u64).div_ceil(tile_width as u64 * scale) as u32,
+            (height as u64).div_ceil(tile_height as u64 * scale) as u32,
+        ))
+    }
+
+    /// How many pixels each tile repeats from its neighbours on every side that has a neighbour.
+    ///
+    /// The overlap is cropped away when rendering.
+    fn tile_overlap(&self) -> u32 {
+        0
+    }
+
+    /// Retrieves the tile at the given position.
+    ///
+    /// Tiles that do not exist return an error of kind `io::ErrorKind::NotFound`.
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData>;
+
+    /// The most zoomed out LOD
+    fn max_lod(&self) -> usize {
+        self.lod_count().saturating_sub(1)
+    }
+}
+
+/// How many LODs an image needs, halving it until it fits in a single tile
+pub(crate) fn lod_count_for(image_bounds: (u32, u32), tile_size: u32) -> usize {
+    let mut lod_count = 1;
+    let (mut width, mut height) = image_bounds;
+    while width > tile_size || height > tile_size {
+        width = width.div_ceil(2);
+        height = height.div_ceil(2);
+        lod_count += 1;
+    }
+    lod_count
+}
+
+/// Creates an error for a malformed tile source
+pub(crate) fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    error: E,
+) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// The most zoomed in slippy map level whose tile positions fit in the `i32`s tiles are addressed by
+pub(crate) const MAX_ZOOM: i64 = 30;
+
+/// The latitude Web Mercator cuts the world off at, north and south, to make it square
+const MAX_LATITUDE: f64 = 85.05112878;
+
+/// Checks that a zoom range read from a file is one a `SlippyGrid` can address.
+pub(crate) fn check_zoom_range(min_zoom: i64, max_zoom: i64) -> io::Result<()> {
+    if min_zoom < 0 || max_zoom > MAX_ZOOM {
+        return Err(invalid_data(format!(
+            "zoom levels {} to {} are outside of 0 to {}",
+            min_zoom, max_zoom, MAX_ZOOM
+        )));
+    }
+    if min_zoom > max_zoom {
+        return Err(invalid_data(format!(
+            "minimum zoom {} is above the maximum zoom {}",
+            min_zoom, max_zoom
+        )));
+    }
+    Ok(())
+}
+
+/// Maps tile positions onto a slippy map tile grid, where zoom level 0 is a single tile covering the whole world and
+/// every zoom level after it doubles the number of tiles on each axis.
+///
+/// Slippy map tiles are numbered across the whole world, so tile (0, 0) is placed at `origin`, the top left tile
+/// of the data in `min_zoom`.
+#[derive(Clone, Copy)]
+pub(crate) struct SlippyGrid {
+    pub min_zoom: i64,
+    pub max_zoom: i64,
+    pub origin: (i64, i64),
+}
+
+impl SlippyGrid {
+    pub fn lod_count(&self) -> usize {
+        (self.max_zoom - self.min_zoom) as usize + 1
+    }
+
+    /// Returns the XYZ `(z, x, y)` of a tile, or None if it is outside of the world.
+    pub fn tile(&self, x: i32, y: i32, lod: usize) -> Option<(i64, i64, i64)> {
+        let z = self.max_zoom - lod as i64;
+        let shift = z - self.min_zoom;
+        if shift < 0 {
+            return None;
+        }
+
+        let x = x as i64 + (self.origin.0 << shift);
+        let y = y as i64 + (self.origin.1 << shift);
+        if x < 0 || y < 0 || x >= 1 << z || y >= 1 << z {
+            return None;
+        }
+
+        Some((z, x, y))
+    }
+
+    /// The size in LOD 0 pixels of the area from `origin` to the given longitude and latitude.
+    pub fn pixels_to(&self, lon: f64, lat: f64, tile_size: (u32, u32)) -> (u32, u32) {
+        let (world_x, world_y) = lon_lat_to_tile(lon, lat, self.max_zoom);
+
+        let shift = self.max_zoom - self.min_zoom;
+        let x = (world_x - (self.origin.0 << shift) as f64) * tile_size.0 as f64;
+        let y = (world_y - (self.origin.1 << shift) as f64) * tile_size.1 as f64;
+        (x.max(0.0).ceil() as u32, y.max(0.0).ceil() as u32)
+    }
+}
+
+/// The position of a longitude and latitude in Web Mercator tiles of the given zoom level. Latitudes beyond the edges of
+/// the projection are clamped to them, as the poles themselves are infinitely far away.
+pub(crate) fn lon_lat_to_tile(lon: f64, lat: f64, zoom: i64) -> (f64, f64) {
+    let tiles_across = (1i64 << zoom) as f64;
+    let lon = lon.clamp(-180.0, 180.0);
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.0) / 360.0 * tiles_across;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0 * tiles_across;
+    // the clamped latitude can land a rounding error past the edge of the world
+    (x, y.clamp(0.0, tiles_across))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poles_are_clamped_to_the_edges_of_the_projection() {
+        let (_, north) = lon_lat_to_tile(0.0, 90.0, 2);
+        let (_, south) = lon_lat_to_tile(0.0, -90.0, 2);
+        assert!(north.abs() < 1e-6);
+        assert!((south - 4.0).abs() < 1e-6);
+
+        let grid = SlippyGrid {
+            min_zoom: 0,
+            max_zoom: 2,
+            origin: (0, 0),
+        };
+        assert_eq!(grid.pixels_to(180.0, -90.0, (256, 256)), (1024, 1024));
+    }
+
+    #[test]
+    fn unaddressable_zoom_ranges_are_rejected() {
+        assert!(check_zoom_range(0, 0).is_ok());
+        assert!(check_zoom_range(3, 14).is_ok());
+        assert!(check_zoom_range(5, 4).is_err());
+        assert!(check_zoom_range(-1, 4).is_err());
+        assert!(check_zoom_range(0, 63).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use super::{check_zoom_range, invalid_data, lon_lat_to_tile, SlippyGrid, TileData, TileSource};
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const HEADER_LENGTH: u64 = 127;
+
+/// How deep leaf directories may be nested before an archive is considered malformed
+const MAX_DIRECTORY_DEPTH: usize = 4;
+
+/// The most bytes a compressed directory or tile may decompress to, so a malformed archive can't exhaust memory
+const MAX_DECOMPRESSED_LENGTH: u64 = 64 * 1024 * 1024;
+
+/// Something a PMTiles archive can be read from, one byte range at a time
+pub trait RangeReader: Send + Sync {
+    /// Reads `length` bytes starting at `offset`
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Vec<u8>>;
+}
+
+/// Reads byte ranges from a local file
+pub struct FileRangeReader {
+    file: Mutex<File>,
+}
+
+impl FileRangeReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(FileRangeReader {
+            file: Mutex::new(File::open(path)?),
+        })
+    }
+}
+
+impl RangeReader for FileRangeReader {
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let mut file = self.file.lock().unwrap();
+
+        // the range comes from the archive itself, so check it before allocating a buffer for it
+        let file_length = file.metadata()?.len();
+        if offset
+            .checked_add(length)
+            .is_none_or(|end| end > file_length)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "PMTiles byte range is past the end of the file",
+            ));
+        }
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0; length as usize];
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+}
+
+impl Compression {
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            // 0 is "unknown", which in practice means uncompressed
+            0 | 1 => Ok(Compression::None),
+            2 => Ok(Compression::Gzip),
+            _ => Err(invalid_data("unsupported PMTiles compression")),
+        }
+    }
+
+    fn decompress(&self, bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes),
+            Compression::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(bytes.as_slice())
+                    .take(MAX_DECOMPRESSED_LENGTH + 1)
+                    .read_to_end(&mut decompressed)?;
+                if decompressed.len() as u64 > MAX_DECOMPRESSED_LENGTH {
+                    return Err(invalid_data("PMTiles data decompresses to too many bytes"));
+                }
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+/// The parts of the PMTiles v3 header that are needed to find tiles
+struct Header {
+    root_dir_offset: u64,
+    root_dir_length: u64,
+    leaf_dirs_offset: u64,
+    tile_data_offset: u64,
+    internal_compression: Compression,
+    tile_compression: Compression,
+    min_zoom: u8,
+    max_zoom: u8,
+    min_lon: f64,
+    min_lat: f64,
+    max_lon: f64,
+    max_lat: f64,
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < HEADER_LENGTH as usize || !bytes.starts_with(b"PMTiles") {
+            return Err(invalid_data("not a PMTiles archive"));
+        }
+        if bytes[7] != 3 {
+            return Err(invalid_data("only PMTiles version 3 is supported"));
+        }
+
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let degrees_at =
+            |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as f64 / 1e7;
+
+        // tile types are 1: MVT, 2: PNG, 3: JPEG, 4: WebP, 5: AVIF. Only raster types can be shown.
+        if bytes[99] == 1 {
+            return Err(invalid_data("vector PMTiles archives are not supported"));
+        }
+
+        Ok(Header {
+            root_dir_offset: u64_at(8),
+            root_dir_length: u64_at(16),
+            leaf_dirs_offset: u64_at(40),
+            tile_data_offset: u64_at(56),
+            internal_compression: Compression::from_byte(bytes[97])?,
+            tile_compression: Compression::from_byte(bytes[98])?,
+            min_zoom: bytes[100],
+            max_zoom: bytes[101],
+            min_lon: degrees_at(102),
+            min_lat: degrees_at(106),
+            max_lon: degrees_at(110),
+            max_lat: degrees_at(114),
+        })
+    }
+}
+
+/// One entry of a PMTiles directory. Entries with a `run_length` of 0 point to a leaf directory instead of a tile.
+#[derive(Clone, Copy)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    run_length: u64,
+}
+
+/// Tiles stored in a PMTiles v3 archive, read with byte range requests.
+pub struct PmTilesSource<R: RangeReader = FileRangeReader> {
+    reader: R,
+    header: Header,
+    /// Parsed directories, by their offset in the archive
+    directories: Mutex<HashMap<u64, Arc<Vec<Entry>>>>,
+    tile_size: (u32, u32),
+    image_bounds: (u32, u32),
+    grid: SlippyGrid,
+}
+
+impl PmTilesSource<FileRangeReader> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        PmTilesSource::from_reader(FileRangeReader::open(path)?)
+    }
+}
+
+impl<R: RangeReader> PmTilesSource<R> {
+    pub fn from_reader(reader: R) -> io::Result<Self> {
+        let header = Header::parse(&reader.read_range(0, HEADER_LENGTH)?)?;
+
+        let min_zoom = header.min_zoom as i64;
+        let max_zoom = header.max_zoom as i64;
+        check_zoom_range(min_zoom, max_zoom)?;
+        let origin = lon_lat_to_tile(header.min_lon, header.max_lat, min_zoom);
+        let grid = SlippyGrid {
+            min_zoom,
+            max_zoom,
+            origin: (origin.0.floor() as i64, origin.1.floor() as i64),
+        };
+
+        let mut source = PmTilesSource {
+            reader,
+            header,
+            directories: Mutex::new(HashMap::new()),
+            tile_size: (0, 0),
+            image_bounds: (0, 0),
+            grid,
+        };
+
+        // get the dimensions of the first tile in the archive
+        source.tile_size = {
+            let (offset, length) = source
+                .first_tile()?
+                .ok_or_else(|| invalid_data("PMTiles archive contains no tiles"))?;
+            let tile = source.read_tile_data(offset, length)?;
+            let tile = image::load_from_memory(&tile).map_err(invalid_data)?;
+            (tile.width(), tile.height())
+        };
+        source.image_bounds = grid.pixels_to(
+            source.header.max_lon,
+            source.header.min_lat,
+            source.tile_size,
+        );
+
+        Ok(source)
+    }
+
+    /// Reads and caches the directory at the given offset
+    fn directory(&self, offset: u64, length: u64) -> io::Result<Arc<Vec<Entry>>> {
+        if let Some(entries) = self.directories.lock().unwrap().get(&offset) {
+            return Ok(entries.clone());
+        }
+
+        let bytes = self
+            .header
+            .internal_compression
+            .decompress(self.reader.read_range(offset, length)?)?;
+        let entries = Arc::new(parse_directory(&bytes)?);
+
+        self.directories
+            .lock()
+            .unwrap()
+            .insert(offset, entries.clone());
+        Ok(entries)
+    }
+
+    /// Finds the offset and length of a tile in the tile data section
+    fn find_tile(&self, tile_id: u64) -> io::Result<Option<(u64, u64)>> {
+        let mut dir_offset = self.header.root_dir_offset;
+        let mut dir_length = self.header.root_dir_length;
+
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            let entries = self.directory(dir_offset, dir_length)?;
+
+            // the entry with the largest tile id not past the one we are looking for
+            let index = entries.partition_point(|entry| entry.tile_id <= tile_id);
+            let entry = match index.checked_sub(1) {
+                Some(index) => entries[index],
+                None => return Ok(None),
+            };
+
+            if entry.run_length == 0 {
+                dir_offset = offset_in(self.header.leaf_dirs_offset, entry.offset)?;
+                dir_length = entry.length;
+            } else if tile_id < entry.tile_id + entry.run_length {
+                return Ok(Some((entry.offset, entry.length)));
+            } else {
+                return Ok(None);
+            }
+        }
+
+        Err(invalid_data("PMTiles leaf directories are nested too deep"))
+    }
+
+    /// Finds the offset and length of the first tile in the archive
+    fn first_tile(&self) -> io::Result<Option<(u64, u64)>> {
+        let mut dir_offset = self.header.root_dir_offset;
+        let mut dir_length = self.header.root_dir_length;
+
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            let entry = match self.directory(dir_offset, dir_length)?.first() {
+                Some(entry) => *entry,
+                None => return Ok(None),
+            };
+
+            if entry.run_length == 0 {
+                dir_offset = offset_in(self.header.leaf_dirs_offset, entry.offset)?;
+                dir_length = entry.length;
+            } else {
+                return Ok(Some((entry.offset, entry.length)));
+            }
+        }
+
+        Err(invalid_data("PMTiles leaf directories are nested too deep"))
+    }
+
+    fn read_tile_data(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        self.header.tile_compression.decompress(
+            self.reader
+                .read_range(offset_in(self.header.tile_data_offset, offset)?, length)?,
+        )
+    }
+}
+
+impl<R: RangeReader> TileSource for PmTilesSource<R> {
+    fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    fn lod_count(&self) -> usize {
+        self.grid.lod_count()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let (z, x, y) = self.grid.tile(x, y, lod).ok_or(io::ErrorKind::NotFound)?;
+        let (offset, length) = self
+            .find_tile(zxy_to_tile_id(z as u8, x as u64, y as u64))?
+            .ok_or(io::ErrorKind::NotFound)?;
+
+        Ok(TileData::Encoded(self.read_tile_data(offset, length)?))
+    }
+}
+
+/// The offset in the archive of something at `offset` in the section starting at `section_offset`
+fn offset_in(section_offset: u64, offset: u64) -> io::Result<u64> {
+    section_offset
+        .checked_add(offset)
+        .ok_or_else(|| invalid_data("PMTiles offset is past the end of the file"))
+}
+
+/// Converts a tile position to its PMTiles tile id, which orders tiles by zoom level and then along a Hilbert curve.
+fn zxy_to_tile_id(z: u8, x: u64, y: u64) -> u64 {
+    // the number of tiles in all zoom levels before this one
+    let base_id = ((1u64 << (2 * z as u64)) - 1) / 3;
+
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x, y);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from((x & s) > 0);
+        let ry = u64::from((y & s) > 0);
+        d += s * s * ((3 * rx) ^ ry);
+
+        // rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    base_id + d
+}
+
+fn parse_directory(bytes: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut bytes = bytes;
+    let num_entries = read_varint(&mut bytes)?;
+
+    // every entry takes at least one byte for each of its four fields
+    if num_entries > bytes.len() as u64 / 4 {
+        return Err(invalid_data("truncated PMTiles directory"));
+    }
+    let num_entries = num_entries as usize;
+
+    // tile ids are stored as deltas from the previous one
+    let mut entries = Vec::with_capacity(num_entries);
+    let mut tile_id = 0;
+    for _ in 0..num_entries {
+        tile_id += read_varint(&mut bytes)?;
+        entries.push(Entry {
+            tile_id,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        });
+    }
+
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(&mut bytes)?;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(&mut bytes)?;
+    }
+
+    // an offset of 0 means the tile directly follows the previous one, otherwise it is stored plus one
+    for i in 0..entries.len() {
+        let value = read_varint(&mut bytes)?;
+        entries[i].offset = if value == 0 && i > 0 {
+            entries[i - 1].offset + entries[i - 1].length
+        } else {
+            value
+                .checked_sub(1)
+                .ok_or_else(|| invalid_data("invalid PMTiles directory offset"))?
+        };
+    }
+
+    Ok(entries)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let (byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| invalid_data("truncated PMTiles directory"))?;
+        *bytes = rest;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+
+        shift += 7;
+        if shift >= 64 {
+            return Err(invalid_data("invalid varint in PMTiles directory"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_ids_follow_zoom_levels_then_the_hilbert_curve() {
+        assert_eq!(zxy_to_tile_id(0, 0, 0), 0);
+        assert_eq!(zxy_to_tile_id(1, 0, 0), 1);
+        assert_eq!(zxy_to_tile_id(1, 0, 1), 2);
+        assert_eq!(zxy_to_tile_id(1, 1, 1), 3);
+        assert_eq!(zxy_to_tile_id(1, 1, 0), 4);
+        assert_eq!(zxy_to_tile_id(2, 0, 0), 5);
+        assert_eq!(zxy_to_tile_id(12, 3423, 1763), 19078479);
+    }
+
+    #[test]
+    fn varints_are_read_and_consumed() {
+        let mut bytes: &[u8] = &[0x00, 0x96, 0x01, 0x7f];
+        assert_eq!(read_varint(&mut bytes).unwrap(), 0);
+        assert_eq!(read_varint(&mut bytes).unwrap(), 150);
+        assert_eq!(read_varint(&mut bytes).unwrap(), 127);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn malformed_varints_are_rejected() {
+        let mut truncated: &[u8] = &[0x80];
+        assert!(read_varint(&mut truncated).is_err());
+
+        let mut too_long: &[u8] = &[0xff; 11];
+        assert!(read_varint(&mut too_long).is_err());
+    }
+
+    #[test]
+    fn directories_are_parsed() {
+        // two entries: tile ids 5 and 6, run lengths 1 and 0, lengths 100 and 50, and the second entry's offset
+        // following on from the first
+        let entries = parse_directory(&[2, 5, 1, 1, 0, 100, 50, 1, 0]).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].tile_id, 5);
+        assert_eq!(entries[0].run_length, 1);
+        assert_eq!(entries[0].length, 100);
+        assert_eq!(entries[0].offset, 0);
+
+        assert_eq!(entries[1].tile_id, 6);
+        assert_eq!(entries[1].run_length, 0);
+        assert_eq!(entries[1].length, 50);
+        assert_eq!(entries[1].offset, 100);
+    }
+
+    #[test]
+    fn directories_with_more_entries_than_bytes_are_rejected() {
+        // claims 2^35 entries, which would abort on allocation if it were trusted
+        assert!(parse_directory(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 0, 0, 0, 0]).is_err());
+        assert!(parse_directory(&[2, 5, 1, 1, 0, 100, 50, 1]).is_err());
+    }
+}
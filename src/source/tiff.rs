@@ -0,0 +1,483 @@
+use super::{invalid_data, TileData, TileSource};
+use ::tiff::decoder::{ChunkType, Decoder, Limits};
+use ::tiff::tags::{
+    CompressionMethod, PhotometricInterpretation, PlanarConfiguration, Predictor, SampleFormat, Tag,
+};
+use ::tiff::ColorType;
+use flate2::read::ZlibDecoder;
+use image::imageops;
+use image::RgbaImage;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// Bit of the NewSubfileType tag marking an IFD as a transparency mask rather than an image
+const SUBFILE_TYPE_MASK: u32 = 4;
+
+/// One IFD of the TIFF, used as one LOD
+struct Level {
+    dimensions: (u32, u32),
+    encoding: Encoding,
+    /// Where each tile's compressed bytes start in the file, with tiles in row-major order
+    tile_offsets: Vec<u64>,
+    /// How many compressed bytes each tile has
+    tile_byte_counts: Vec<u64>,
+}
+
+/// How the tiles of an IFD are compressed and what their pixels are
+struct Encoding {
+    compression: CompressionMethod,
+    predictor: Predictor,
+    photometric: PhotometricInterpretation,
+    /// The color type of the decompressed pixels. JPEG tiles stored as YCbCr are converted to RGB when decompressed.
+    color_type: ColorType,
+    /// Tables shared by every JPEG compressed tile of the IFD
+    jpeg_tables: Option<Vec<u8>>,
+}
+
+/// A tiled, pyramidal TIFF such as a Cloud-Optimized GeoTIFF, or a BigTIFF with internal overviews.
+///
+/// The full resolution image is LOD 0, and every overview that is a power of two smaller is used as the matching
+/// LOD. The IFDs and their tile offsets are read when the file is opened, and tiles are then read with positional
+/// reads and decompressed one at a time as they are read, so several threads can read tiles at once.
+pub struct TiffSource {
+    file: File,
+    file_length: u64,
+    big_endian: bool,
+    tile_size: (u32, u32),
+    /// The IFD used for each LOD, if the file has one for it
+    levels: Vec<Option<Level>>,
+}
+
+impl TiffSource {
+    /// Whether a path is a TIFF this source can open
+    pub fn is_tiff(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("tif") || ext.eq_ignore_ascii_case("tiff"))
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_length = file.metadata()?.len();
+
+        let mut byte_order = [0; 2];
+        read_exact_at(&file, &mut byte_order, 0)?;
+        let big_endian = &byte_order == b"MM";
+
+        let mut decoder = Decoder::new(BufReader::new(file.try_clone()?))
+            .map_err(tiff_error)?
+            // tile offset tables of large files are well beyond the default limits
+            .with_limits(Limits::unlimited());
+
+        // find the dimensions and tiles of every image in the file
+        let mut images = Vec::new();
+        for ifd in 0.. {
+            if ifd > 0 {
+                if !decoder.more_images() {
+                    break;
+                }
+                decoder.next_image().map_err(tiff_error)?;
+            }
+
+            let is_mask = decoder
+                .find_tag_unsigned::<u32>(Tag::NewSubfileType)
+                .map_err(tiff_error)?
+                .is_some_and(|subfile_type| subfile_type & SUBFILE_TYPE_MASK != 0);
+            let is_planar = decoder
+                .find_tag_unsigned::<u16>(Tag::PlanarConfiguration)
+                .map_err(tiff_error)?
+                .and_then(PlanarConfiguration::from_u16)
+                == Some(PlanarConfiguration::Planar);
+            if is_mask || is_planar || !matches!(decoder.get_chunk_type(), ChunkType::Tile) {
+                continue;
+            }
+
+            images.push((
+                decoder.dimensions().map_err(tiff_error)?,
+                decoder.chunk_dimensions(),
+                read_encoding(&mut decoder)?,
+                decoder
+                    .get_tag_u64_vec(Tag::TileOffsets)
+                    .map_err(tiff_error)?,
+                decoder
+                    .get_tag_u64_vec(Tag::TileByteCounts)
+                    .map_err(tiff_error)?,
+            ));
+        }
+
+        let (full_dimensions, tile_size) = images
+            .iter()
+            .map(|(dimensions, tile_size, ..)| (*dimensions, *tile_size))
+            .max_by_key(|((width, height), _)| *width as u64 * *height as u64)
+            .ok_or_else(|| invalid_data("TIFF contains no tiled images"))?;
+
+        // use every image that is a power of two smaller than the full image, with the same tiles, as a LOD
+        let mut levels: Vec<Option<Level>> = Vec::new();
+        for (dimensions, image_tile_size, encoding, tile_offsets, tile_byte_counts) in images {
+            if image_tile_size != tile_size {
+                continue;
+            }
+
+            let lod = (full_dimensions.0 as f64 / dimensions.0 as f64)
+                .log2()
+                .round() as usize;
+            if lod >= 32 || full_dimensions.0.div_ceil(1 << lod).abs_diff(dimensions.0) > 1 {
+                continue;
+            }
+
+            if levels.len() <= lod {
+                levels.resize_with(lod + 1, || None);
+            }
+            if levels[lod].is_none() {
+                levels[lod] = Some(Level {
+                    dimensions,
+                    encoding,
+                    tile_offsets,
+                    tile_byte_counts,
+                });
+            }
+        }
+
+        Ok(TiffSource {
+            file,
+            file_length,
+            big_endian,
+            tile_size,
+            levels,
+        })
+    }
+
+    /// Reads a tile's compressed bytes and decompresses them into 8 bit samples, in `encoding.color_type`'s layout.
+    /// The samples cover the whole tile, including any padding past the edge of the image.
+    fn decompress_tile(&self, level: &Level, chunk_index: usize) -> io::Result<Vec<u8>> {
+        let (offset, byte_count) = level
+            .tile_offsets
+            .get(chunk_index)
+            .zip(level.tile_byte_counts.get(chunk_index))
+            .ok_or_else(|| invalid_data("TIFF has fewer tile offsets than tiles"))?;
+        if offset
+            .checked_add(*byte_count)
+            .is_none_or(|end| end > self.file_length)
+        {
+            return Err(invalid_data("TIFF tile is past the end of the file"));
+        }
+        let mut compressed = vec![0; *byte_count as usize];
+        read_exact_at(&self.file, &mut compressed, *offset)?;
+
+        let encoding = &level.encoding;
+        let bytes = match encoding.compression {
+            CompressionMethod::None => compressed,
+            CompressionMethod::LZW => {
+                weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+                    .decode(&compressed)
+                    .map_err(invalid_data)?
+            }
+            CompressionMethod::Deflate | CompressionMethod::OldDeflate => {
+                let mut bytes = Vec::new();
+                ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut bytes)?;
+                bytes
+            }
+            // JPEG decodes straight to 8 bit samples, and isn't combined with a predictor
+            CompressionMethod::ModernJPEG => return decode_jpeg(&compressed, encoding),
+            _ => return Err(invalid_data("unsupported TIFF compression")),
+        };
+
+        let (bits, samples_per_pixel) = sample_layout(encoding.color_type)?;
+        let row_samples = self.tile_size.0 as usize * samples_per_pixel;
+        let horizontal_predictor = encoding.predictor == Predictor::Horizontal;
+        match bits {
+            8 => {
+                let mut samples = bytes;
+                if horizontal_predictor {
+                    undo_horizontal_predictor(&mut samples, row_samples, samples_per_pixel);
+                }
+                Ok(samples)
+            }
+            16 => {
+                let mut samples: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|bytes| match self.big_endian {
+                        true => u16::from_be_bytes([bytes[0], bytes[1]]),
+                        false => u16::from_le_bytes([bytes[0], bytes[1]]),
+                    })
+                    .collect();
+                if horizontal_predictor {
+                    undo_horizontal_predictor(&mut samples, row_samples, samples_per_pixel);
+                }
+                // reduce 16 bit samples to 8 bits
+                Ok(samples.iter().map(|sample| (sample >> 8) as u8).collect())
+            }
+            _ => Err(invalid_data("unsupported TIFF sample format")),
+        }
+    }
+}
+
+impl TileSource for TiffSource {
+    fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    fn lod_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        self.levels[0].as_ref().map(|level| level.dimensions)
+    }
+
+    fn tile_grid(&self, lod: usize) -> Option<(u32, u32)> {
+        let level = self.levels.get(lod)?.as_ref()?;
+        Some((
+            level.dimensions.0.div_ceil(self.tile_size.0),
+            level.dimensions.1.div_ceil(self.tile_size.1),
+        ))
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let level = self
+            .levels
+            .get(lod)
+            .and_then(|level| level.as_ref())
+            .ok_or(io::ErrorKind::NotFound)?;
+
+        let (tiles_across, tiles_down) = self.tile_grid(lod).ok_or(io::ErrorKind::NotFound)?;
+        if x < 0 || y < 0 || x as u32 >= tiles_across || y as u32 >= tiles_down {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let chunk_index = y as u32 * tiles_across + x as u32;
+
+        let samples = self.decompress_tile(level, chunk_index as usize)?;
+        let tile = to_rgba(
+            samples,
+            level.encoding.color_type,
+            self.tile_size.0,
+            self.tile_size.1,
+        )?;
+
+        // tiles on the right and bottom edges are padded past the edge of the image
+        let width = self
+            .tile_size
+            .0
+            .min(level.dimensions.0 - x as u32 * self.tile_size.0);
+        let height = self
+            .tile_size
+            .1
+            .min(level.dimensions.1 - y as u32 * self.tile_size.1);
+        if (width, height) == self.tile_size {
+            Ok(TileData::Decoded(tile))
+        } else {
+            Ok(TileData::Decoded(
+                imageops::crop_imm(&tile, 0, 0, width, height).to_image(),
+            ))
+        }
+    }
+}
+
+/// Reads how the tiles of the decoder's current IFD are encoded
+fn read_encoding(decoder: &mut Decoder<BufReader<File>>) -> io::Result<Encoding> {
+    let compression = decoder
+        .find_tag_unsigned::<u16>(Tag::Compression)
+        .map_err(tiff_error)?
+        .map_or(
+            CompressionMethod::None,
+            CompressionMethod::from_u16_exhaustive,
+        );
+    let predictor = decoder
+        .find_tag_unsigned::<u16>(Tag::Predictor)
+        .map_err(tiff_error)?
+        .and_then(Predictor::from_u16)
+        .unwrap_or(Predictor::None);
+    let photometric = decoder
+        .find_tag_unsigned::<u16>(Tag::PhotometricInterpretation)
+        .map_err(tiff_error)?
+        .and_then(PhotometricInterpretation::from_u16)
+        .ok_or_else(|| invalid_data("TIFF is missing its photometric interpretation"))?;
+    // palette and inverted grayscale images would decode to the wrong colors, so they are rejected up front
+    if !matches!(
+        photometric,
+        PhotometricInterpretation::BlackIsZero
+            | PhotometricInterpretation::RGB
+            | PhotometricInterpretation::YCbCr
+    ) {
+        return Err(invalid_data(format!(
+            "unsupported TIFF photometric interpretation {:?}",
+            photometric
+        )));
+    }
+    let sample_formats = decoder
+        .find_tag_unsigned_vec::<u16>(Tag::SampleFormat)
+        .map_err(tiff_error)?
+        .unwrap_or_default();
+    if let Some(sample_format) = sample_formats
+        .into_iter()
+        .map(SampleFormat::from_u16_exhaustive)
+        .find(|sample_format| *sample_format != SampleFormat::Uint)
+    {
+        return Err(invalid_data(format!(
+            "unsupported TIFF sample format {:?}, only unsigned integer samples are supported",
+            sample_format
+        )));
+    }
+    let jpeg_tables = match compression {
+        CompressionMethod::ModernJPEG => decoder
+            .find_tag(Tag::JPEGTables)
+            .map_err(tiff_error)?
+            .map(|_| decoder.get_tag_u8_vec(Tag::JPEGTables))
+            .transpose()
+            .map_err(tiff_error)?,
+        _ => None,
+    };
+
+    // the JPEG decoder converts YCbCr to RGB, which is how GDAL stores JPEG compressed COGs by default
+    let color_type = match (decoder.colortype().map_err(tiff_error)?, compression) {
+        (ColorType::YCbCr(8), CompressionMethod::ModernJPEG) => ColorType::RGB(8),
+        (color_type, _) => color_type,
+    };
+    match sample_layout(color_type)? {
+        (8 | 16, _) => {}
+        (bits, _) => {
+            return Err(invalid_data(format!(
+                "unsupported TIFF bit depth {}, only 8 and 16 bit samples are supported",
+                bits
+            )))
+        }
+    }
+
+    Ok(Encoding {
+        compression,
+        predictor,
+        photometric,
+        color_type,
+        jpeg_tables,
+    })
+}
+
+/// Decodes a JPEG compressed tile, with the IFD's shared tables if it has them
+fn decode_jpeg(compressed: &[u8], encoding: &Encoding) -> io::Result<Vec<u8>> {
+    // both the tables and the tile are complete JPEG streams, so the tables' end marker and the tile's start marker
+    // are left out when joining them
+    let stream = match &encoding.jpeg_tables {
+        Some(tables) if tables.len() >= 2 && compressed.len() >= 2 => {
+            let mut stream = tables[..tables.len() - 2].to_vec();
+            stream.extend_from_slice(&compressed[2..]);
+            stream
+        }
+        _ => compressed.to_vec(),
+    };
+
+    let mut decoder = jpeg_decoder::Decoder::new(stream.as_slice());
+    decoder.set_color_transform(match encoding.photometric {
+        PhotometricInterpretation::RGB => jpeg_decoder::ColorTransform::RGB,
+        PhotometricInterpretation::YCbCr => jpeg_decoder::ColorTransform::YCbCr,
+        _ => jpeg_decoder::ColorTransform::None,
+    });
+    decoder.decode().map_err(invalid_data)
+}
+
+/// The bits per sample and samples per pixel of a color type
+fn sample_layout(color_type: ColorType) -> io::Result<(u8, usize)> {
+    match color_type {
+        ColorType::RGBA(bits) => Ok((bits, 4)),
+        ColorType::RGB(bits) => Ok((bits, 3)),
+        ColorType::GrayA(bits) => Ok((bits, 2)),
+        ColorType::Gray(bits) => Ok((bits, 1)),
+        _ => Err(invalid_data(format!(
+            "unsupported TIFF color type {:?}",
+            color_type
+        ))),
+    }
+}
+
+/// Turns the differences stored by TIFF's horizontal predictor back into samples, one row at a time
+fn undo_horizontal_predictor<T: Copy + WrappingAdd>(
+    samples: &mut [T],
+    row_samples: usize,
+    samples_per_pixel: usize,
+) {
+    for row in samples.chunks_mut(row_samples) {
+        for i in samples_per_pixel..row.len() {
+            row[i] = row[i].wrapping_add(row[i - samples_per_pixel]);
+        }
+    }
+}
+
+trait WrappingAdd {
+    fn wrapping_add(self, other: Self) -> Self;
+}
+
+impl WrappingAdd for u8 {
+    fn wrapping_add(self, other: Self) -> Self {
+        u8::wrapping_add(self, other)
+    }
+}
+
+impl WrappingAdd for u16 {
+    fn wrapping_add(self, other: Self) -> Self {
+        u16::wrapping_add(self, other)
+    }
+}
+
+/// Converts 8 bit samples of a tile to RGBA
+fn to_rgba(
+    mut samples: Vec<u8>,
+    color_type: ColorType,
+    width: u32,
+    height: u32,
+) -> io::Result<RgbaImage> {
+    let (_, samples_per_pixel) = sample_layout(color_type)?;
+    let sample_count = width as usize * height as usize * samples_per_pixel;
+    if samples.len() < sample_count {
+        return Err(invalid_data("TIFF tile has the wrong number of pixels"));
+    }
+    samples.truncate(sample_count);
+
+    let rgba: Vec<u8> = match samples_per_pixel {
+        4 => samples,
+        3 => samples
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        2 => samples
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        _ => samples
+            .iter()
+            .flat_map(|gray| [*gray, *gray, *gray, 255])
+            .collect(),
+    };
+
+    RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| invalid_data("TIFF tile has the wrong number of pixels"))
+}
+
+/// Reads exactly `bytes.len()` bytes at `offset` without moving a shared cursor, so threads can read at once
+#[cfg(unix)]
+fn read_exact_at(file: &File, bytes: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, bytes, offset)
+}
+
+/// Reads exactly `bytes.len()` bytes at `offset` without moving a shared cursor, so threads can read at once
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut bytes: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !bytes.is_empty() {
+        match file.seek_read(bytes, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                bytes = &mut bytes[read..];
+                offset += read as u64;
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+fn tiff_error(error: ::tiff::TiffError) -> io::Error {
+    match error {
+        ::tiff::TiffError::IoError(error) => error,
+        error => invalid_data(error.to_string()),
+    }
+}
@@ -0,0 +1,115 @@
+use super::{invalid_data, TileData, TileSource};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many tiles Zoomify puts in each `TileGroup` directory
+const TILES_PER_GROUP: u64 = 256;
+
+/// A Zoomify export. A directory with an `ImageProperties.xml` and tiles stored as
+/// `TileGroup<n>/<tier>-<col>-<row>.jpg`.
+///
+/// Zoomify numbers its tiers from the smallest (a single tile) to the largest, so the full resolution tier is LOD 0
+/// here and every tier below it is one LOD higher.
+pub struct ZoomifySource {
+    dir: PathBuf,
+    tile_size: u32,
+    image_bounds: (u32, u32),
+    /// Columns and rows of tiles in each tier, from the smallest tier to the largest
+    tier_grids: Vec<(u64, u64)>,
+}
+
+impl ZoomifySource {
+    /// Whether a directory contains a Zoomify export
+    pub fn is_zoomify_dir(dir: &Path) -> bool {
+        dir.join("ImageProperties.xml").is_file()
+    }
+
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let xml = fs::read_to_string(dir.join("ImageProperties.xml"))?;
+        let doc = roxmltree::Document::parse(&xml).map_err(invalid_data)?;
+
+        let properties = doc.root_element();
+        let attribute = |name: &str| {
+            properties
+                .attribute(name)
+                .and_then(|value| value.parse::<u32>().ok())
+                .ok_or_else(|| invalid_data(format!("missing or invalid {} attribute", name)))
+        };
+        let width = attribute("WIDTH")?;
+        let height = attribute("HEIGHT")?;
+        let tile_size = attribute("TILESIZE")?;
+        if tile_size == 0 {
+            return Err(invalid_data("TILESIZE must not be 0"));
+        }
+
+        // every tier halves the one above it, until the image fits in a single tile. Each tier's grid is the full size
+        // divided by the area a tile covers at that tier, rounded up, as Zoomify itself computes it.
+        let mut tier_grids = Vec::new();
+        let mut tile_span = tile_size as u64;
+        loop {
+            tier_grids.push((
+                (width as u64).div_ceil(tile_span),
+                (height as u64).div_ceil(tile_span),
+            ));
+            if tile_span >= width as u64 && tile_span >= height as u64 {
+                break;
+            }
+            tile_span *= 2;
+        }
+        tier_grids.reverse();
+
+        Ok(ZoomifySource {
+            dir: dir.to_path_buf(),
+            tile_size,
+            image_bounds: (width, height),
+            tier_grids,
+        })
+    }
+}
+
+impl TileSource for ZoomifySource {
+    fn tile_size(&self) -> (u32, u32) {
+        (self.tile_size, self.tile_size)
+    }
+
+    fn lod_count(&self) -> usize {
+        self.tier_grids.len()
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn tile_grid(&self, lod: usize) -> Option<(u32, u32)> {
+        let tier = self.tier_grids.len().checked_sub(lod + 1)?;
+        let (columns, rows) = self.tier_grids[tier];
+        Some((columns as u32, rows as u32))
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let tier = self
+            .tier_grids
+            .len()
+            .checked_sub(lod + 1)
+            .ok_or(io::ErrorKind::NotFound)?;
+        let (columns, rows) = self.tier_grids[tier];
+        if x < 0 || y < 0 || x as u64 >= columns || y as u64 >= rows {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        // tiles are numbered across all tiers from the smallest one, and split into groups in that order
+        let tiles_before_tier: u64 = self.tier_grids[..tier]
+            .iter()
+            .map(|(columns, rows)| columns * rows)
+            .sum();
+        let tile_index = tiles_before_tier + y as u64 * columns + x as u64;
+
+        let path = self
+            .dir
+            .join(format!("TileGroup{}", tile_index / TILES_PER_GROUP))
+            .join(format!("{}-{}-{}.jpg", tier, x, y));
+
+        Ok(TileData::Encoded(fs::read(path)?))
+    }
+}
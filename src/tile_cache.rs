@@ -0,0 +1,302 @@
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+use tileview::tiler::TilingSettings;
+
+/// File in each cache entry whose modification time records when the entry was last used. Entries without one are
+/// still being generated, or were abandoned part way through.
+const LAST_USED_FILE: &str = "last_used";
+
+/// File in each unfinished entry holding the ID of the process generating it
+const LOCK_FILE: &str = "lock";
+
+/// How old an unfinished entry must be before it is assumed to have been abandoned, even if a process with the ID in
+/// its lock file is still running
+const ABANDONED_ENTRY_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What `TileCache::entry()` found for an image
+pub enum CacheEntry {
+    /// The directory of tiles generated by an earlier launch
+    Complete(PathBuf),
+    /// An empty directory to generate the tiles into
+    Empty(PendingEntry),
+    /// Another instance is generating the tiles right now
+    InProgress,
+}
+
+/// An entry this process is generating the tiles of. Unless `finish()` is called, it is removed again when dropped, so
+/// a conversion that fails or panics doesn't leave it behind.
+pub struct PendingEntry {
+    cache: TileCache,
+    dir: PathBuf,
+    finished: bool,
+}
+
+impl PendingEntry {
+    /// The directory to generate the tiles into
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Marks the entry's tiles as all generated, then makes room for them by removing the least recently used entries.
+    pub fn finish(mut self) -> io::Result<()> {
+        File::create(self.dir.join(LAST_USED_FILE))?;
+        self.finished = true;
+        fs::remove_file(self.dir.join(LOCK_FILE))?;
+        self.cache.clean_up(&self.dir)
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Tile pyramids generated from image files, kept between launches so the same image isn't converted again.
+///
+/// Each image's tiles are stored in `<hash>-<tile size>-<ext>`, where the hash is of the image file's contents, with
+/// `-q<quality>` appended for lossy formats. Once the cache grows beyond its size limit, the least recently used
+/// entries are removed.
+#[derive(Clone)]
+pub struct TileCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl TileCache {
+    /// The cache in `$XDG_CACHE_HOME/tileview`, or `~/.cache/tileview` if that isn't set. None if neither is known.
+    pub fn open_default(max_bytes: u64) -> Option<Self> {
+        let cache_home = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+        Some(TileCache {
+            dir: cache_home.join("tileview"),
+            max_bytes,
+        })
+    }
+
+    /// Finds the directory an image's tiles are cached in, for the given tiling settings.
+    ///
+    /// If the tiles haven't been generated yet, the directory is created empty and locked to this process so they can
+    /// be generated into it. Unfinished entries are only replaced once they have been abandoned, since another instance
+    /// may still be generating them.
+    pub fn entry(&self, image_path: &Path, settings: &TilingSettings) -> io::Result<CacheEntry> {
+        let mut name = format!(
+            "{}-{}-{}",
+            hash_file(image_path)?,
+            settings.tile_size,
+            settings.format.extension()
+        );
+        if settings.format.is_lossy() {
+            name += &format!("-q{}", settings.quality);
+        }
+        let entry = self.dir.join(name);
+
+        let last_used = entry.join(LAST_USED_FILE);
+        if last_used.is_file() {
+            File::create(last_used)?;
+            return Ok(CacheEntry::Complete(entry));
+        }
+
+        if entry.exists() {
+            if !is_abandoned(&entry) {
+                return Ok(CacheEntry::InProgress);
+            }
+            // left behind by a conversion that didn't finish
+            fs::remove_dir_all(&entry)?;
+        }
+
+        // creating the directory claims it, in case another instance is looking for the same entry
+        fs::create_dir_all(&self.dir)?;
+        match fs::create_dir(&entry) {
+            Ok(()) => {
+                // dropping the pending entry removes it again if the lock can't be written
+                let pending = PendingEntry {
+                    cache: self.clone(),
+                    dir: entry,
+                    finished: false,
+                };
+                fs::write(pending.dir.join(LOCK_FILE), process::id().to_string())?;
+                Ok(CacheEntry::Empty(pending))
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                Ok(CacheEntry::InProgress)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Removes the least recently used entries until the cache fits in its size limit, never removing `keep`.
+    fn clean_up(&self, keep: &Path) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let mut total_bytes = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let bytes = dir_size(&path)?;
+            total_bytes += bytes;
+
+            // unfinished entries go first, unless they may still be being generated
+            let last_used = fs::metadata(path.join(LAST_USED_FILE))
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if path != keep && (last_used.is_some() || is_abandoned(&path)) {
+                entries.push((last_used.unwrap_or(SystemTime::UNIX_EPOCH), path, bytes));
+            }
+        }
+
+        entries.sort();
+        for (_, path, bytes) in entries {
+            if total_bytes <= self.max_bytes {
+                break;
+            }
+            fs::remove_dir_all(&path)?;
+            total_bytes -= bytes;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether an unfinished entry was left behind, either because the process in its lock file is no longer running or
+/// because it hasn't been written to for long enough that it must have been
+fn is_abandoned(entry: &Path) -> bool {
+    let owner = fs::read_to_string(entry.join(LOCK_FILE))
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok());
+    if owner.is_some_and(|pid| !is_running(pid)) {
+        return true;
+    }
+
+    fs::metadata(entry)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > ABANDONED_ENTRY_AGE)
+}
+
+/// Whether a process is running. Only Linux lists processes where this can check, so elsewhere every process is
+/// assumed to be running and abandoned entries are found by their age alone.
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+/// Hex encoded SHA-256 of a file's contents
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// The total size of the files in a directory and its subdirectories
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut bytes = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        bytes += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn cache_with_image() -> (TempDir, TileCache, PathBuf) {
+        let temp_dir = TempDir::new("tile-cache-test").unwrap();
+        let image_path = temp_dir.path().join("image.png");
+        fs::write(&image_path, b"not really a png").unwrap();
+        let cache = TileCache {
+            dir: temp_dir.path().join("cache"),
+            max_bytes: u64::MAX,
+        };
+        (temp_dir, cache, image_path)
+    }
+
+    #[test]
+    fn entries_being_generated_are_in_progress_until_finished() {
+        let (_temp_dir, cache, image_path) = cache_with_image();
+        let settings = TilingSettings::default();
+
+        let Ok(CacheEntry::Empty(pending)) = cache.entry(&image_path, &settings) else {
+            panic!("expected an empty entry");
+        };
+        assert!(matches!(
+            cache.entry(&image_path, &settings),
+            Ok(CacheEntry::InProgress)
+        ));
+
+        let dir = pending.dir().to_path_buf();
+        pending.finish().unwrap();
+        assert!(matches!(
+            cache.entry(&image_path, &settings),
+            Ok(CacheEntry::Complete(complete)) if complete == dir
+        ));
+    }
+
+    #[test]
+    fn dropped_entries_are_removed() {
+        let (_temp_dir, cache, image_path) = cache_with_image();
+        let settings = TilingSettings::default();
+
+        let Ok(CacheEntry::Empty(pending)) = cache.entry(&image_path, &settings) else {
+            panic!("expected an empty entry");
+        };
+        let dir = pending.dir().to_path_buf();
+        drop(pending);
+        assert!(!dir.exists());
+        assert!(matches!(
+            cache.entry(&image_path, &settings),
+            Ok(CacheEntry::Empty(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn entries_locked_by_exited_processes_are_replaced() {
+        let (_temp_dir, cache, image_path) = cache_with_image();
+        let settings = TilingSettings::default();
+
+        let Ok(CacheEntry::Empty(pending)) = cache.entry(&image_path, &settings) else {
+            panic!("expected an empty entry");
+        };
+        // leave the entry behind as if this process had exited while generating it
+        let dir = pending.dir().to_path_buf();
+        std::mem::forget(pending);
+        let exited = process::Command::new("true").spawn().unwrap();
+        let exited_pid = exited.id();
+        exited.wait_with_output().unwrap();
+        fs::write(dir.join(LOCK_FILE), exited_pid.to_string()).unwrap();
+
+        assert!(matches!(
+            cache.entry(&image_path, &settings),
+            Ok(CacheEntry::Empty(_))
+        ));
+    }
+}
@@ -0,0 +1,306 @@
+//! Converts image files to tile pyramids.
+//!
+//! This was done with `tileproc`'s `gen_tiles_to_dir` and `generate_lods` before. Those block until the whole pyramid
+//! is written, write every full resolution tile before building the coarser LODs from them, report no progress and
+//! only write PNG tiles. Tiling here instead lets a viewer show the image while it is being converted, coarsest LOD
+//! first, draw a progress bar, and pick the tile format and quality.
+
+use crate::source::{lod_count_for, TileData, TileSource};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The file format a `Tiler` writes tiles in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileFormat {
+    Png,
+    /// Lossy, and without transparency
+    Jpeg,
+    /// Lossy, with transparency
+    WebP,
+}
+
+impl TileFormat {
+    /// The file extension of tiles in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TileFormat::Png => "png",
+            TileFormat::Jpeg => "jpg",
+            TileFormat::WebP => "webp",
+        }
+    }
+
+    /// Whether tiles in this format are encoded with a quality setting
+    pub fn is_lossy(&self) -> bool {
+        *self != TileFormat::Png
+    }
+}
+
+impl FromStr for TileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(TileFormat::Png),
+            "jpeg" | "jpg" => Ok(TileFormat::Jpeg),
+            "webp" => Ok(TileFormat::WebP),
+            _ => Err(format!(
+                "unknown tile format \"{}\", expected png, jpeg or webp",
+                s
+            )),
+        }
+    }
+}
+
+/// How a `Tiler` cuts and encodes tiles
+#[derive(Clone, Copy, Debug)]
+pub struct TilingSettings {
+    /// The width and height of each tile, in pixels
+    pub tile_size: u32,
+    pub format: TileFormat,
+    /// The quality lossy formats are encoded with, from 1 to 100
+    pub quality: u8,
+}
+
+impl Default for TilingSettings {
+    fn default() -> Self {
+        TilingSettings {
+            tile_size: 256,
+            format: TileFormat::Png,
+            quality: 90,
+        }
+    }
+}
+
+/// How far along a `Tiler` is, shared between the thread running it and anyone watching.
+#[derive(Default)]
+pub struct TilingProgress {
+    tiles_done: AtomicUsize,
+    tiles_total: AtomicUsize,
+    finished: AtomicBool,
+}
+
+impl TilingProgress {
+    /// How many tiles have been written so far
+    pub fn tiles_done(&self) -> usize {
+        self.tiles_done.load(Ordering::Relaxed)
+    }
+
+    /// How many tiles there are to write in total
+    pub fn tiles_total(&self) -> usize {
+        self.tiles_total.load(Ordering::Relaxed)
+    }
+
+    /// How much of the work is done, from 0 to 1
+    pub fn fraction(&self) -> f32 {
+        match self.tiles_total() {
+            0 => 0.0,
+            total => self.tiles_done() as f32 / total as f32,
+        }
+    }
+
+    /// Whether the tiler has stopped, either because every tile is written or because it failed
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+}
+
+/// Cuts an image file into a pyramid of tiles stored as `<lod>/<x>,<y>.<ext>`, the layout `DirSource` reads.
+///
+/// The coarsest LOD is written first, shrunk straight from the decoded image, so that a viewer reading the tiles as they
+/// are written (see `source()`) shows the whole image quickly. The other LODs follow from the full resolution one up,
+/// each written as soon as it has been halved from the one below, so only two LOD images are ever held at once.
+pub struct Tiler {
+    image_path: PathBuf,
+    tile_dir: PathBuf,
+    settings: TilingSettings,
+    image_bounds: (u32, u32),
+    lod_count: usize,
+    progress: Arc<TilingProgress>,
+}
+
+impl Tiler {
+    /// Prepares to tile an image into `tile_dir`. Only the image's header is read until `run()` is called.
+    pub fn new(image_path: &Path, tile_dir: &Path, settings: TilingSettings) -> io::Result<Self> {
+        let image_bounds = image::image_dimensions(image_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let tiler = Tiler {
+            image_path: image_path.to_path_buf(),
+            tile_dir: tile_dir.to_path_buf(),
+            settings,
+            image_bounds,
+            lod_count: lod_count_for(image_bounds, settings.tile_size),
+            progress: Arc::new(TilingProgress::default()),
+        };
+
+        let tiles_total = (0..tiler.lod_count)
+            .map(|lod| {
+                let (columns, rows) = tiler.source().tile_grid(lod).unwrap();
+                columns as usize * rows as usize
+            })
+            .sum();
+        tiler
+            .progress
+            .tiles_total
+            .store(tiles_total, Ordering::Relaxed);
+
+        Ok(tiler)
+    }
+
+    pub fn progress(&self) -> Arc<TilingProgress> {
+        self.progress.clone()
+    }
+
+    /// A source that reads the tiles as they are written. Tiles that haven't been written yet return an error of
+    /// kind `io::ErrorKind::WouldBlock`.
+    pub fn source(&self) -> TilingSource {
+        TilingSource {
+            tile_dir: self.tile_dir.clone(),
+            tile_size: self.settings.tile_size,
+            ext: self.settings.format.extension(),
+            image_bounds: self.image_bounds,
+            lod_count: self.lod_count,
+            progress: self.progress.clone(),
+        }
+    }
+
+    /// Decodes the image and writes every tile, coarsest LOD first and then the rest from the full resolution one up.
+    pub fn run(self) -> io::Result<()> {
+        let result = self.write_tiles();
+        self.progress.finished.store(true, Ordering::Release);
+        result
+    }
+
+    fn write_tiles(&self) -> io::Result<()> {
+        let image = image::open(&self.image_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .to_rgba8();
+
+        // halving an image n times rounds its size up to the same as dividing it by 2^n once
+        let coarsest = self.lod_count - 1;
+        if coarsest > 0 {
+            let scale = 1 << coarsest;
+            let thumbnail = imageops::thumbnail(
+                &image,
+                image.width().div_ceil(scale),
+                image.height().div_ceil(scale),
+            );
+            self.write_lod(coarsest, &thumbnail)?;
+        }
+
+        // every other LOD is the one below it, halved
+        let mut lod_image = image;
+        for lod in 0..coarsest.max(1) {
+            if lod > 0 {
+                lod_image = imageops::resize(
+                    &lod_image,
+                    lod_image.width().div_ceil(2),
+                    lod_image.height().div_ceil(2),
+                    FilterType::Triangle,
+                );
+            }
+            self.write_lod(lod, &lod_image)?;
+        }
+
+        Ok(())
+    }
+
+    /// Cuts a LOD's image into tiles and writes them
+    fn write_lod(&self, lod: usize, lod_image: &RgbaImage) -> io::Result<()> {
+        let lod_dir = self.tile_dir.join(lod.to_string());
+        fs::create_dir_all(&lod_dir)?;
+
+        let tile_size = self.settings.tile_size;
+        let ext = self.settings.format.extension();
+        for tile_y in 0..lod_image.height().div_ceil(tile_size) {
+            for tile_x in 0..lod_image.width().div_ceil(tile_size) {
+                let x = tile_x * tile_size;
+                let y = tile_y * tile_size;
+                let tile = imageops::crop_imm(
+                    lod_image,
+                    x,
+                    y,
+                    tile_size.min(lod_image.width() - x),
+                    tile_size.min(lod_image.height() - y),
+                )
+                .to_image();
+
+                // write under another name first, so readers never see a half written tile
+                let path = lod_dir.join(format!("{},{}.{}", tile_x, tile_y, ext));
+                let partial_path = lod_dir.join(format!("{},{}.{}.partial", tile_x, tile_y, ext));
+                self.save_tile(tile, &partial_path)?;
+                fs::rename(&partial_path, &path)?;
+
+                self.progress.tiles_done.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save_tile(&self, tile: RgbaImage, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let quality = self.settings.quality.clamp(1, 100);
+        match self.settings.format {
+            TileFormat::Png => tile
+                .write_to(&mut writer, ImageFormat::Png)
+                .map_err(io::Error::other)?,
+            TileFormat::Jpeg => JpegEncoder::new_with_quality(&mut writer, quality)
+                .encode_image(&DynamicImage::ImageRgba8(tile).into_rgb8())
+                .map_err(io::Error::other)?,
+            TileFormat::WebP => writer.write_all(
+                &webp::Encoder::from_rgba(&tile, tile.width(), tile.height())
+                    .encode(quality as f32),
+            )?,
+        }
+        writer.flush()
+    }
+}
+
+/// The tiles of a `Tiler`, readable while it is still writing them.
+pub struct TilingSource {
+    tile_dir: PathBuf,
+    tile_size: u32,
+    ext: &'static str,
+    image_bounds: (u32, u32),
+    lod_count: usize,
+    progress: Arc<TilingProgress>,
+}
+
+impl TileSource for TilingSource {
+    fn tile_size(&self) -> (u32, u32) {
+        (self.tile_size, self.tile_size)
+    }
+
+    fn lod_count(&self) -> usize {
+        self.lod_count
+    }
+
+    fn image_bounds(&self) -> Option<(u32, u32)> {
+        Some(self.image_bounds)
+    }
+
+    fn read_tile(&self, x: i32, y: i32, lod: usize) -> io::Result<TileData> {
+        let path = self
+            .tile_dir
+            .join(lod.to_string())
+            .join(format!("{},{}.{}", x, y, self.ext));
+
+        // check whether the tiler has finished before reading, so a tile it writes in between isn't missed
+        let finished = self.progress.is_finished();
+        match fs::read(path) {
+            Ok(bytes) => Ok(TileData::Encoded(bytes)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !finished => {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
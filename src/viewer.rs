@@ -0,0 +1,759 @@
+use crate::camera::{
+    get_screen_sectors, lod_from_zoom, tile_on_screen, world_pos_to_screen_pos, CameraSettings,
+};
+use crate::loader::{default_thread_count, LoadOutcome, TileLoader};
+use crate::source::TileSource;
+use macroquad::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+
+/// How many tiles may be handed to each worker thread at once. Tiles beyond that wait in the viewer's queue, where
+/// they can still be dropped if they are no longer wanted.
+const TILES_IN_FLIGHT_PER_THREAD: usize = 2;
+
+/// How many seconds ahead of the camera's current motion tiles are prefetched
+const PREFETCH_LOOKAHEAD_SECONDS: f32 = 0.5;
+
+/// How much of the previously measured camera motion is kept each frame, to smooth out uneven input
+const MOTION_SMOOTHING: f32 = 0.8;
+
+/// How many doublings of zoom per second count as zooming, rather than being left over from smoothing
+const MIN_ZOOM_SPEED: f32 = 0.1;
+
+/// How many LODs coarser than the current one the fallback tiles kept under the view are
+const FALLBACK_LOD_STEPS: usize = 2;
+
+/// How long to wait before retrying a tile that failed to load for the first time. Doubles with every failure after.
+const FIRST_RETRY_DELAY_SECONDS: f64 = 0.5;
+
+/// The longest to wait before retrying a tile that failed to load
+const MAX_RETRY_DELAY_SECONDS: f64 = 30.0;
+
+/// How long to wait before asking again for a tile the source doesn't have ready yet
+const NOT_READY_RETRY_DELAY_SECONDS: f64 = 0.25;
+
+/// How much memory the texture cache may use if not told otherwise
+pub const DEFAULT_CACHE_BUDGET_BYTES: usize = 512 * 1024 * 1024;
+
+/// What a cached tile without a texture counts as against the cache budget, so that missing tiles are evicted too
+const MISSING_TILE_BYTES: usize = 64;
+
+/// A tile in the texture cache
+struct CachedTile {
+    /// None if the tile doesn't exist or could not be decoded
+    texture: Option<Texture2D>,
+    /// The last frame the tile was on screen
+    last_used: u64,
+}
+
+impl CachedTile {
+    /// How much memory the tile's texture uses
+    fn size_bytes(&self) -> usize {
+        match self.texture {
+            Some(texture) => texture.width() as usize * texture.height() as usize * 4,
+            None => MISSING_TILE_BYTES,
+        }
+    }
+}
+
+/// A tile that failed to load, and so isn't in the texture cache
+struct FailedTile {
+    /// How many times in a row loading the tile has failed
+    attempts: u32,
+    /// When the tile may be requested again
+    retry_at: f64,
+}
+
+/// Settings for a `TileViewer`
+#[derive(Clone, Debug)]
+pub struct ViewerSettings {
+    /// How many worker threads read and decode tiles
+    pub decode_threads: usize,
+    /// How many bytes of textures to keep cached before evicting the least recently used tiles
+    pub cache_budget_bytes: usize,
+}
+
+impl Default for ViewerSettings {
+    fn default() -> Self {
+        ViewerSettings {
+            decode_threads: default_thread_count(),
+            cache_budget_bytes: DEFAULT_CACHE_BUDGET_BYTES,
+        }
+    }
+}
+
+/// A wanted tile that isn't cached or being loaded. Ordered so the tile to load first is the greatest.
+struct QueuedTile {
+    tile: (i32, i32, usize),
+    /// Whether the tile is only predicted to be needed, rather than needed for the current view
+    prefetch: bool,
+    /// How far the tile's center is from the center of the view it is wanted for, in world pixels
+    distance: f32,
+}
+
+impl Ord for QueuedTile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // tiles for the current view first, then coarser LODs, then from the center of the view outwards
+        other
+            .prefetch
+            .cmp(&self.prefetch)
+            .then(self.tile.2.cmp(&other.tile.2))
+            .then(other.distance.total_cmp(&self.distance))
+    }
+}
+
+impl PartialOrd for QueuedTile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedTile {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedTile {}
+
+/// determine if current desired view is fully cached and ready to be rendered
+/// Limits a range of sectors to a LOD's tile grid, if it is known, since there are no tiles outside of it
+fn clamp_sectors_to_grid(
+    (top_left_sector, bottom_right_sector): ((i32, i32), (i32, i32)),
+    tile_grid: Option<(u32, u32)>,
+) -> ((i32, i32), (i32, i32)) {
+    match tile_grid {
+        Some((columns, rows)) => (
+            (top_left_sector.0.max(0), top_left_sector.1.max(0)),
+            (
+                bottom_right_sector.0.min(columns as i32 - 1),
+                bottom_right_sector.1.min(rows as i32 - 1),
+            ),
+        ),
+        None => (top_left_sector, bottom_right_sector),
+    }
+}
+
+fn current_view_cached(
+    texture_cache: &HashMap<(i32, i32, usize), CachedTile>,
+    render_lod: usize,
+    camera: &CameraSettings,
+    tile_dimensions: (f32, f32),
+    tile_grid: Option<(u32, u32)>,
+) -> bool {
+    // determine what sectors we need to render
+    let (top_left_sector, bottom_right_sector) = clamp_sectors_to_grid(
+        get_screen_sectors(camera, tile_dimensions, render_lod),
+        tile_grid,
+    );
+
+    let mut fully_rendered = true;
+    for sector_y in top_left_sector.1..=bottom_right_sector.1 {
+        for sector_x in top_left_sector.0..=bottom_right_sector.0 {
+            // render texture
+            if !texture_cache.contains_key(&(sector_x, sector_y, render_lod)) {
+                fully_rendered = false;
+                break;
+            }
+        }
+        if !fully_rendered {
+            break;
+        }
+    }
+
+    fully_rendered
+}
+
+fn average(numbers: &VecDeque<f64>) -> f64 {
+    numbers.iter().sum::<f64>() / numbers.len() as f64
+}
+
+fn new_rolling_average(new_value: f64, rolling_buffer: &mut VecDeque<f64>) -> f64 {
+    rolling_buffer.push_back(new_value);
+
+    if rolling_buffer.len() > 100 {
+        rolling_buffer.pop_front();
+    }
+
+    average(rolling_buffer)
+}
+
+/// Renders the tiles of a `TileSource` to the screen, retrieving and caching them as the camera moves.
+pub struct TileViewer<S: TileSource + ?Sized> {
+    source: Arc<S>,
+    texture_cache: HashMap<(i32, i32, usize), CachedTile>,
+    /// How much memory the textures in texture_cache use
+    cache_bytes: usize,
+    cache_budget_bytes: usize,
+    /// Counts calls to `update()`, for tracking when tiles were last used
+    frame: u64,
+    /// The `frame_start_time` of the latest call to `update()`
+    frame_start_time: f64,
+    loader: TileLoader,
+    /// Tiles waiting to be handed to the loader, rebuilt every frame
+    tile_queue: BinaryHeap<QueuedTile>,
+    /// Tiles handed to the loader that haven't come back yet, with the flags that cancel them
+    tiles_in_flight: HashMap<(i32, i32, usize), Arc<AtomicBool>>,
+    /// Wanted tiles that failed to load, waiting to be retried
+    failed_tiles: HashMap<(i32, i32, usize), FailedTile>,
+    /// Wanted tiles the source didn't have ready yet, and when to ask for them again
+    not_ready_tiles: HashMap<(i32, i32, usize), f64>,
+    max_tiles_in_flight: usize,
+    tile_dimensions: (f32, f32),
+    tile_overlap: f32,
+    max_lod: usize,
+    /// The columns and rows of tiles in each LOD, if the source knows them
+    tile_grids: Vec<Option<(u32, u32)>>,
+    rolling_upload_buffer: VecDeque<f64>,
+    rolling_average_upload_time: f64,
+    /// The camera at the previous update, and when it was
+    last_camera: Option<(CameraSettings, f64)>,
+    /// How many world pixels per second the camera is panning
+    pan_velocity: (f32, f32),
+    /// How many times per second the zoom is doubling, negative when zooming out
+    zoom_velocity: f32,
+}
+
+impl<S: TileSource + ?Sized + 'static> TileViewer<S> {
+    pub fn new(source: Arc<S>) -> Self {
+        TileViewer::with_settings(source, ViewerSettings::default())
+    }
+
+    pub fn with_settings(source: Arc<S>, settings: ViewerSettings) -> Self {
+        let decode_threads = settings.decode_threads;
+        let (tile_width, tile_height) = source.tile_size();
+        TileViewer {
+            texture_cache: HashMap::new(),
+            cache_bytes: 0,
+            cache_budget_bytes: settings.cache_budget_bytes,
+            frame: 0,
+            frame_start_time: 0.0,
+            loader: TileLoader::new(source.clone(), decode_threads),
+            tile_queue: BinaryHeap::new(),
+            tiles_in_flight: HashMap::new(),
+            failed_tiles: HashMap::new(),
+            not_ready_tiles: HashMap::new(),
+            max_tiles_in_flight: decode_threads.max(1) * TILES_IN_FLIGHT_PER_THREAD,
+            tile_dimensions: (tile_width as f32, tile_height as f32),
+            tile_overlap: source.tile_overlap() as f32,
+            max_lod: source.max_lod(),
+            tile_grids: (0..=source.max_lod())
+                .map(|lod| source.tile_grid(lod))
+                .collect(),
+            rolling_upload_buffer: VecDeque::new(),
+            rolling_average_upload_time: 0.0,
+            last_camera: None,
+            pan_velocity: (0.0, 0.0),
+            zoom_velocity: 0.0,
+            source,
+        }
+    }
+
+    /// The source tiles are read from
+    pub fn source(&self) -> &Arc<S> {
+        &self.source
+    }
+
+    /// The most zoomed out LOD of the source
+    pub fn max_lod(&self) -> usize {
+        self.max_lod
+    }
+
+    /// The dimensions of a tile in pixels
+    pub fn tile_dimensions(&self) -> (f32, f32) {
+        self.tile_dimensions
+    }
+
+    /// The dimensions of the full resolution image in pixels, if the source knows them
+    pub fn image_bounds(&self) -> Option<(u32, u32)> {
+        self.source.image_bounds()
+    }
+
+    /// Updates the tile cache for the current camera. Should be called once per frame.
+    ///
+    /// Queues tiles needed for the current view for the worker threads, followed by tiles the camera is moving
+    /// towards, uploads as many of the tiles they have decoded as possible before `frame_time_limit` seconds have
+    /// passed since `frame_start_time`, then evicts the least recently used tiles if the cache is over budget.
+    pub fn update(
+        &mut self,
+        camera: &CameraSettings,
+        frame_start_time: f64,
+        frame_time_limit: f64,
+    ) {
+        self.frame += 1;
+        self.frame_start_time = frame_start_time;
+        self.track_camera_motion(camera, frame_start_time);
+        self.mark_tiles_on_screen_used(camera);
+        self.queue_wanted_tiles(camera);
+        self.dispatch_queued_tiles();
+        self.upload_tiles_till_out_of_work_or_time(frame_start_time, frame_time_limit);
+        self.evict_least_recently_used_tiles();
+    }
+
+    /// How many of the tiles wanted for the current view failed to load, and are waiting to be retried
+    pub fn failed_tile_count(&self) -> usize {
+        self.failed_tiles.len()
+    }
+
+    /// How much memory the cached textures use, in bytes
+    pub fn cache_size_bytes(&self) -> usize {
+        self.cache_bytes
+    }
+
+    /// Measures how fast the camera is panning and zooming since the previous update
+    fn track_camera_motion(&mut self, camera: &CameraSettings, time: f64) {
+        if let Some((last_camera, last_time)) = self.last_camera {
+            let elapsed = (time - last_time) as f32;
+            if elapsed > 0.0 {
+                let pan_velocity = (
+                    (camera.x_offset - last_camera.x_offset) / elapsed,
+                    (camera.y_offset - last_camera.y_offset) / elapsed,
+                );
+                let zoom_velocity =
+                    (camera.zoom_multiplier / last_camera.zoom_multiplier).log2() / elapsed;
+
+                self.pan_velocity = (
+                    self.pan_velocity.0 * MOTION_SMOOTHING
+                        + pan_velocity.0 * (1.0 - MOTION_SMOOTHING),
+                    self.pan_velocity.1 * MOTION_SMOOTHING
+                        + pan_velocity.1 * (1.0 - MOTION_SMOOTHING),
+                );
+                self.zoom_velocity = self.zoom_velocity * MOTION_SMOOTHING
+                    + zoom_velocity * (1.0 - MOTION_SMOOTHING);
+            }
+        }
+        self.last_camera = Some((*camera, time));
+    }
+
+    /// Rebuilds tile_queue from the tiles wanted this frame that aren't cached or being loaded, and cancels loading
+    /// tiles that are no longer wanted.
+    ///
+    /// The tiles of the current LOD on screen are wanted, along with fallbacks to draw while they load: the tiles of a
+    /// coarser LOD under the view, and every tile of the coarsest LOD. These are followed by tiles the camera is
+    /// predicted to need soon. While panning, these are the tiles of the current LOD where the camera is heading.
+    /// While zooming, these are the tiles of the next finer or coarser LOD under where the view is heading.
+    fn queue_wanted_tiles(&mut self, camera: &CameraSettings) {
+        self.tile_queue.clear();
+        let mut wanted_tiles = HashSet::new();
+
+        let lod = lod_from_zoom(camera.zoom_multiplier, self.max_lod);
+        self.queue_sectors(camera, lod, false, &mut wanted_tiles);
+
+        let fallback_lod = (lod + FALLBACK_LOD_STEPS).min(self.max_lod);
+        self.queue_sectors(camera, fallback_lod, false, &mut wanted_tiles);
+
+        // the whole coarsest LOD, if the source knows how big the image is. Otherwise just what's on screen of it.
+        match self.tile_grids[self.max_lod] {
+            Some((columns, rows)) => {
+                for sector_y in 0..rows as i32 {
+                    for sector_x in 0..columns as i32 {
+                        self.queue_tile(
+                            (sector_x, sector_y, self.max_lod),
+                            camera,
+                            false,
+                            &mut wanted_tiles,
+                        );
+                    }
+                }
+            }
+            None => self.queue_sectors(camera, self.max_lod, false, &mut wanted_tiles),
+        }
+
+        let predicted_camera = CameraSettings {
+            x_offset: camera.x_offset + self.pan_velocity.0 * PREFETCH_LOOKAHEAD_SECONDS,
+            y_offset: camera.y_offset + self.pan_velocity.1 * PREFETCH_LOOKAHEAD_SECONDS,
+            zoom_multiplier: camera.zoom_multiplier
+                * (self.zoom_velocity * PREFETCH_LOOKAHEAD_SECONDS).exp2(),
+        };
+
+        // the ring of tiles the camera is panning towards, ignoring tiles already on screen
+        let pan_screen_distance = self.pan_velocity.0.hypot(self.pan_velocity.1)
+            * PREFETCH_LOOKAHEAD_SECONDS
+            * camera.zoom_multiplier;
+        if pan_screen_distance >= 1.0 {
+            let panned_camera = CameraSettings {
+                zoom_multiplier: camera.zoom_multiplier,
+                ..predicted_camera
+            };
+            self.queue_sectors(&panned_camera, lod, true, &mut wanted_tiles);
+        }
+
+        // the LOD the camera is zooming towards
+        let next_lod = if self.zoom_velocity > MIN_ZOOM_SPEED {
+            lod.checked_sub(1)
+        } else if self.zoom_velocity < -MIN_ZOOM_SPEED && lod < self.max_lod {
+            Some(lod + 1)
+        } else {
+            None
+        };
+        if let Some(next_lod) = next_lod {
+            self.queue_sectors(&predicted_camera, next_lod, true, &mut wanted_tiles);
+        }
+
+        // forget failures of tiles that have left the view, they start over if they come back
+        self.failed_tiles
+            .retain(|tile, _| wanted_tiles.contains(tile));
+        self.not_ready_tiles
+            .retain(|tile, _| wanted_tiles.contains(tile));
+
+        // cancel tiles that have left the view, or take back the cancellation if they came back before being read
+        for (tile, cancelled) in &self.tiles_in_flight {
+            cancelled.store(!wanted_tiles.contains(tile), atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Queues the tiles of a LOD a camera would see, if they aren't cached or being loaded already
+    ///
+    /// Only tiles inside the LOD's tile grid are queued, when it is known.
+    fn queue_sectors(
+        &mut self,
+        camera: &CameraSettings,
+        lod: usize,
+        prefetch: bool,
+        wanted_tiles: &mut HashSet<(i32, i32, usize)>,
+    ) {
+        let (top_left_sector, bottom_right_sector) = clamp_sectors_to_grid(
+            get_screen_sectors(camera, self.tile_dimensions, lod),
+            self.tile_grids[lod],
+        );
+
+        for sector_y in top_left_sector.1..=bottom_right_sector.1 {
+            for sector_x in top_left_sector.0..=bottom_right_sector.0 {
+                self.queue_tile((sector_x, sector_y, lod), camera, prefetch, wanted_tiles);
+            }
+        }
+    }
+
+    /// Queues a tile wanted for a camera's view, if it isn't cached or being loaded already, nor waiting to be retried
+    fn queue_tile(
+        &mut self,
+        tile: (i32, i32, usize),
+        camera: &CameraSettings,
+        prefetch: bool,
+        wanted_tiles: &mut HashSet<(i32, i32, usize)>,
+    ) {
+        if self.texture_cache.contains_key(&tile)
+            || !wanted_tiles.insert(tile)
+            || self.tiles_in_flight.contains_key(&tile)
+            || self
+                .failed_tiles
+                .get(&tile)
+                .is_some_and(|failed_tile| failed_tile.retry_at > self.frame_start_time)
+            || self
+                .not_ready_tiles
+                .get(&tile)
+                .is_some_and(|retry_at| *retry_at > self.frame_start_time)
+        {
+            return;
+        }
+
+        let two: f32 = 2.0;
+        let (sector_x, sector_y, lod) = tile;
+        let tile_world_width = self.tile_dimensions.0 * two.powf(lod as f32);
+        let tile_world_height = self.tile_dimensions.1 * two.powf(lod as f32);
+        let distance = ((sector_x as f32 + 0.5) * tile_world_width - camera.x_offset)
+            .hypot((sector_y as f32 + 0.5) * tile_world_height - camera.y_offset);
+
+        self.tile_queue.push(QueuedTile {
+            tile,
+            prefetch,
+            distance,
+        });
+    }
+
+    /// Hands the most wanted queued tiles to the worker threads, as long as they don't already have enough to work on.
+    fn dispatch_queued_tiles(&mut self) {
+        while self.tiles_in_flight.len() < self.max_tiles_in_flight {
+            match self.tile_queue.pop() {
+                Some(queued_tile) => {
+                    let cancelled = Arc::new(AtomicBool::new(false));
+                    self.loader.request(queued_tile.tile, cancelled.clone());
+                    self.tiles_in_flight.insert(queued_tile.tile, cancelled);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Marks the cached tiles of every LOD that are on screen as used this frame
+    fn mark_tiles_on_screen_used(&mut self, camera: &CameraSettings) {
+        for (tile, cached_tile) in self.texture_cache.iter_mut() {
+            if tile_on_screen(*tile, camera, self.tile_dimensions) {
+                cached_tile.last_used = self.frame;
+            }
+        }
+    }
+
+    /// Removes the least recently used tiles from texture_cache until it fits in the cache budget
+    ///
+    /// Tiles on screen this frame are never removed, even if they alone are over budget. Nor are tiles of the coarsest
+    /// LOD, which are kept as a fallback to draw wherever finer tiles haven't loaded.
+    fn evict_least_recently_used_tiles(&mut self) {
+        if self.cache_bytes <= self.cache_budget_bytes {
+            return;
+        }
+
+        let mut tiles_by_age: Vec<(u64, (i32, i32, usize))> = self
+            .texture_cache
+            .iter()
+            .filter(|(tile, cached_tile)| {
+                cached_tile.last_used < self.frame && tile.2 != self.max_lod
+            })
+            .map(|(tile, cached_tile)| (cached_tile.last_used, *tile))
+            .collect();
+        tiles_by_age.sort_unstable();
+
+        for (_, tile) in tiles_by_age {
+            if self.cache_bytes <= self.cache_budget_bytes {
+                break;
+            }
+
+            let cached_tile = self.texture_cache.remove(&tile).unwrap();
+            self.cache_bytes -= cached_tile.size_bytes();
+            if let Some(texture) = cached_tile.texture {
+                texture.delete();
+            }
+        }
+    }
+
+    /// Renders image tiles and returns how many are currently being rendered
+    ///
+    /// Renders all image tiles in tile cache that are on screen. Including tiles with an LOD different from the current one,
+    /// unless the current LOD is fully cached. Larger LOD tiles are rendered first, so as to fill in holes left by
+    /// smaller LOD tiles that have not been cached yet.
+    pub fn render(&self, camera: &CameraSettings, tile_boxes: bool, show_culling: bool) -> u32 {
+        let mut num_rendered_tiles: u32 = 0;
+        let two: f32 = 2.0;
+
+        let lod = lod_from_zoom(camera.zoom_multiplier, self.max_lod);
+        let fully_cached = current_view_cached(
+            &self.texture_cache,
+            lod,
+            camera,
+            self.tile_dimensions,
+            self.tile_grids[lod],
+        );
+
+        for render_lod in (0..=self.max_lod).rev() {
+            // other LODs would only be drawn over or under a complete view
+            if fully_cached && render_lod != lod {
+                continue;
+            }
+
+            // determine what sectors we need to render
+            let (top_left_sector, bottom_right_sector) =
+                get_screen_sectors(camera, self.tile_dimensions, render_lod);
+
+            // for all cached tiles
+            for ((tile_x, tile_y, tile_lod), cached_tile) in &self.texture_cache {
+                // if correct LOD
+                if *tile_lod == render_lod {
+                    let tile_on_screen = if !show_culling {
+                        *tile_x >= top_left_sector.0
+                            && *tile_y >= top_left_sector.1
+                            && *tile_x <= bottom_right_sector.0
+                            && *tile_y <= bottom_right_sector.1
+                    } else {
+                        // Define a helper closure for culling logic
+                        let is_within = |coord: i32, start: i32, end: i32| {
+                            if end - start > 1 {
+                                // Multiple tiles on this axis; apply culling
+                                coord > start && coord < end
+                            } else {
+                                // Only one or two tiles on this axis; allow both
+                                coord == start || coord == end
+                            }
+                        };
+
+                        // Check both axes
+                        is_within(*tile_x, top_left_sector.0, bottom_right_sector.0)
+                            && is_within(*tile_y, top_left_sector.1, bottom_right_sector.1)
+                    };
+
+                    // if tile on screen
+                    if tile_on_screen {
+                        // if there's a texture to be rendered
+                        if let Some(texture) = cached_tile.texture {
+                            let lod_scale = two.powf(render_lod as f32);
+                            let tile_world_x = self.tile_dimensions.0 * lod_scale * *tile_x as f32;
+                            let tile_world_y = self.tile_dimensions.1 * lod_scale * *tile_y as f32;
+
+                            let (tile_screen_x, tile_screen_y) =
+                                world_pos_to_screen_pos(tile_world_x, tile_world_y, camera);
+
+                            // crop away the pixels this tile shares with its neighbours
+                            let source_rect = if self.tile_overlap > 0.0 {
+                                let left = if *tile_x > 0 { self.tile_overlap } else { 0.0 };
+                                let top = if *tile_y > 0 { self.tile_overlap } else { 0.0 };
+                                Rect::new(
+                                    left,
+                                    top,
+                                    (texture.width() - left).min(self.tile_dimensions.0),
+                                    (texture.height() - top).min(self.tile_dimensions.1),
+                                )
+                            } else {
+                                Rect::new(0.0, 0.0, texture.width(), texture.height())
+                            };
+
+                            // draw the tile at its own size, edge tiles can be smaller than the rest
+                            let tile_screen_width =
+                                source_rect.w * lod_scale * camera.zoom_multiplier;
+                            let tile_screen_height =
+                                source_rect.h * lod_scale * camera.zoom_multiplier;
+
+                            let params = DrawTextureParams {
+                                dest_size: Some(vec2(tile_screen_width, tile_screen_height)),
+                                source: Some(source_rect),
+                                rotation: 0.,
+                                flip_x: false,
+                                flip_y: false,
+                                pivot: None,
+                            };
+
+                            if camera.zoom_multiplier >= 1.0 {
+                                texture.set_filter(FilterMode::Nearest);
+                            } else {
+                                texture.set_filter(FilterMode::Linear);
+                            }
+
+                            draw_texture_ex(texture, tile_screen_x, tile_screen_y, WHITE, params);
+
+                            if tile_boxes {
+                                // draw red box around newly rendered tile
+
+                                // top
+                                draw_line(
+                                    tile_screen_x,
+                                    tile_screen_y,
+                                    tile_screen_x + tile_screen_width,
+                                    tile_screen_y,
+                                    3.0,
+                                    RED,
+                                );
+                                // bottom
+                                draw_line(
+                                    tile_screen_x,
+                                    tile_screen_y + tile_screen_height,
+                                    tile_screen_x + tile_screen_width,
+                                    tile_screen_y + tile_screen_height,
+                                    3.0,
+                                    RED,
+                                );
+                                // left
+                                draw_line(
+                                    tile_screen_x,
+                                    tile_screen_y,
+                                    tile_screen_x,
+                                    tile_screen_y + tile_screen_height,
+                                    3.0,
+                                    RED,
+                                );
+                                // right
+                                draw_line(
+                                    tile_screen_x + tile_screen_width,
+                                    tile_screen_y,
+                                    tile_screen_x + tile_screen_width,
+                                    tile_screen_y + tile_screen_height,
+                                    3.0,
+                                    RED,
+                                );
+                            }
+
+                            num_rendered_tiles += 1;
+                        }
+                    }
+                }
+            }
+        }
+        num_rendered_tiles
+    }
+
+    /// Uploads tiles decoded by the worker threads to the GPU and stores them in texture_cache
+    ///
+    /// Always uploads at least one tile, assuming at least one has been decoded.
+    ///
+    /// Uploads more tiles if there is time to do so before the next frame needs to be rendered.
+    fn upload_tiles_till_out_of_work_or_time(
+        &mut self,
+        frame_start_time: f64,
+        frame_time_limit: f64,
+    ) {
+        let mut textures_uploaded = 0;
+        loop {
+            // stop if out of time. But only if have uploaded at least one texture
+            if textures_uploaded != 0 {
+                let time_since_last_frame = get_time() - frame_start_time;
+                if time_since_last_frame + self.rolling_average_upload_time > frame_time_limit * 0.7
+                {
+                    break;
+                }
+            }
+
+            let loaded_tile = match self.loader.try_recv() {
+                Some(loaded_tile) => loaded_tile,
+                None => break,
+            };
+            self.tiles_in_flight.remove(&loaded_tile.tile);
+
+            let tile_upload_start_time = get_time();
+            let texture_option = match loaded_tile.outcome {
+                LoadOutcome::Loaded(image) => {
+                    self.failed_tiles.remove(&loaded_tile.tile);
+                    self.not_ready_tiles.remove(&loaded_tile.tile);
+                    Some(Texture2D::from_rgba8(
+                        image.width() as u16,
+                        image.height() as u16,
+                        image.as_raw(),
+                    ))
+                }
+                LoadOutcome::Missing => {
+                    self.failed_tiles.remove(&loaded_tile.tile);
+                    self.not_ready_tiles.remove(&loaded_tile.tile);
+                    None
+                }
+                LoadOutcome::Failed => {
+                    // retry with exponential backoff, the tile may just not be readable yet
+                    let attempts = self
+                        .failed_tiles
+                        .get(&loaded_tile.tile)
+                        .map_or(0, |failed_tile| failed_tile.attempts)
+                        + 1;
+                    let retry_delay = (FIRST_RETRY_DELAY_SECONDS
+                        * 2f64.powi(attempts.min(16) as i32 - 1))
+                    .min(MAX_RETRY_DELAY_SECONDS);
+                    self.failed_tiles.insert(
+                        loaded_tile.tile,
+                        FailedTile {
+                            attempts,
+                            retry_at: get_time() + retry_delay,
+                        },
+                    );
+                    continue;
+                }
+                LoadOutcome::NotReady => {
+                    self.not_ready_tiles
+                        .insert(loaded_tile.tile, get_time() + NOT_READY_RETRY_DELAY_SECONDS);
+                    continue;
+                }
+                LoadOutcome::Cancelled => continue,
+            };
+            let cached_tile = CachedTile {
+                texture: texture_option,
+                last_used: self.frame,
+            };
+            self.cache_bytes += cached_tile.size_bytes();
+            self.texture_cache.insert(loaded_tile.tile, cached_tile);
+
+            if texture_option.is_some() {
+                textures_uploaded += 1;
+
+                let last_time_to_upload = get_time() - tile_upload_start_time;
+
+                self.rolling_average_upload_time =
+                    new_rolling_average(last_time_to_upload, &mut self.rolling_upload_buffer);
+            }
+        }
+    }
+}